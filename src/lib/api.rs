@@ -1,5 +1,17 @@
 use crate::{
-    types::{Anime, Animes, Episode, Episodes},
+    config::Config,
+    disk::{preallocate, DiskOptions},
+    downloads::{hash_source, record, DownloadRecord, Outcome},
+    format::{detect, is_playlist, Probe, VideoFormat},
+    hls::{self, HlsProgress},
+    metadata::{refresh_nfos, tag_episode, write_nfos},
+    mp4::{validate, Mp4Error},
+    partial::{is_busy, part_path, target_path, PartFile},
+    player::Media,
+    pretty_bytes::convert,
+    sanitize::distinct_filename,
+    schedule::{Schedule, Status},
+    types::{clear_title, Anime, Animes, Episode, Episodes, ID},
     ui::{DownloadMessage, Message},
 };
 use base64::decode;
@@ -11,26 +23,40 @@ use block_modes::{BlockMode, Cbc};
 use md5::{Digest, Md5};
 
 use reqwest::{
-    header::{HeaderMap, HeaderValue, CACHE_CONTROL, USER_AGENT},
-    Response,
+    header::{
+        HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_RANGE,
+        LAST_MODIFIED, RANGE, USER_AGENT,
+    },
+    Response, StatusCode,
 };
 
 use std::{
+    collections::HashSet,
     error::Error,
-    fs::{self, OpenOptions},
+    fs,
     io::{prelude::*, SeekFrom},
-    path::Path,
+    mem,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 use tokio::sync::mpsc::Sender;
+use tui::widgets::Text;
 use url::Url;
 
 use serde::{Deserialize, Serialize};
 
-use chrono::{NaiveDate, Utc};
+use chrono::{Local, NaiveDate, Utc};
+use futures::{stream, StreamExt};
+use futures_timer::Delay;
 use serde_json::{de, ser};
 
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
 
+/// Longest wait between two looks at the download schedule.
+const SCHEDULE_POLL: Duration = Duration::from_secs(60);
+/// Wait between two looks at the free disk space while downloads are paused.
+const SPACE_POLL: Duration = Duration::from_secs(30);
+
 static KEY: &[u8] = b"LXgIVP&PorO68Rq7dTx8N^lP!Fa5sGJ^*XK";
 
 static USER_AGENT_VALUE: &'static str = "Mozilla/5.0 (iPhone; CPU iPhone OS 12_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148";
@@ -70,25 +96,17 @@ pub async fn fetch_all_animes() -> Result<Animes, Box<dyn Error>> {
         updated_at: Utc::now().naive_utc().date(),
     }
     .save();
+    // Titles and ids may have changed since the .nfo files were written.
+    if Config::load()?.metadata.nfo {
+        let _ = refresh_nfos(&response);
+    }
     Ok(response)
 }
-pub fn clear_title(s: &str) -> String {
-    s.trim()
-        .replace(&[' ', '\''][..], "-")
-        .replace(
-            &[
-                ' ', '~', '@', '#', '$', '&', '(', ')', '*', '!', '+', '=', ':', ';', ',', '.',
-                '?', '/', '\'',
-            ][..],
-            "",
-        )
-        .to_lowercase()
-}
 
 pub async fn fetch_anime(anime: &Anime) -> Result<Episodes, Box<dyn Error>> {
     let url = Url::parse(&format!(
         "https://twist.moe/api/anime/{}/sources",
-        clear_title(&anime.title)
+        anime.slug()
     ))?;
     let episodes: Episodes = reqwest::Client::new()
         .get(url)
@@ -190,47 +208,471 @@ fn decrypt_data(encrypted_data: &str) -> Result<String, Box<dyn Error>> {
     Ok(decrypted_string)
 }
 
-fn decrypt_source_url(episode: &Episode) -> Result<Url, Box<dyn Error>> {
+pub fn decrypt_source_url(episode: &Episode) -> Result<Url, Box<dyn Error>> {
     let decrypted_path = decrypt_data(&episode.source)?;
     let url_string = format!("https://twist.moe{}", decrypted_path);
     let url = Url::parse(&url_string)?;
     Ok(url)
 }
 
+/// Sanitized name of the anime on disk. Titles that lose characters to
+/// the sanitizer get their id added, so no two animes share a folder.
+pub fn anime_folder(anime: &Anime) -> String {
+    distinct_filename(&anime.title, &anime.id.to_string())
+}
+
+/// Folder an anime's episodes are downloaded to. Folders created before
+/// filenames were sanitized are reused so partial downloads can resume.
+pub fn anime_dir(anime: &Anime) -> PathBuf {
+    let root = Path::new("./animes");
+    let legacy = root.join(clear_title(&anime.title));
+    if legacy.is_dir() {
+        return legacy;
+    }
+    root.join(anime_folder(anime))
+}
+
+/// The downloaded episode in whichever format it was saved as, or where a
+/// new download goes until the server says what it sends.
+pub fn episode_file(anime: &Anime, number: i64) -> PathBuf {
+    let dir = anime_dir(anime);
+    let existing = episode_paths(&dir, number).find(|path| path.is_file());
+    existing.unwrap_or_else(|| dir.join(format!("{}.{}", number, VideoFormat::default())))
+}
+
+/// Part file of an unfinished download of the episode.
+fn episode_part(anime: &Anime, number: i64) -> Option<PathBuf> {
+    episode_paths(&anime_dir(anime), number)
+        .map(|path| part_path(&path))
+        .find(|path| path.is_file())
+}
+
+fn episode_paths(dir: &Path, number: i64) -> impl Iterator<Item = PathBuf> + '_ {
+    VideoFormat::ALL
+        .iter()
+        .map(move |format| dir.join(format!("{}.{}", number, format)))
+}
+
+/// Bytes on disk for the episode, finished or not.
+fn episode_size(anime: &Anime, number: i64) -> u64 {
+    let path = episode_file(anime, number);
+    fs::metadata(&path)
+        .ok()
+        .or_else(|| fs::metadata(episode_part(anime, number)?).ok())
+        .map_or(0, |metadata| metadata.len())
+}
+
+/// Episode numbers that have a file in the anime's download folder.
+pub fn downloaded_episodes(anime: &Anime) -> Vec<i64> {
+    let entries = match fs::read_dir(anime_dir(anime)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut numbers: Vec<i64> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            VideoFormat::from_path(&path)?;
+            path.file_stem()?.to_str()?.parse().ok()
+        })
+        .collect();
+    numbers.sort();
+    numbers
+}
+
+/// IDs of all animes that have a download folder.
+pub fn downloaded_animes(animes: &[Anime]) -> HashSet<ID> {
+    let folders: HashSet<String> = match fs::read_dir("./animes") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect(),
+        Err(_) => return HashSet::new(),
+    };
+
+    animes
+        .iter()
+        .filter(|anime| {
+            folders.contains(&anime_folder(anime)) || folders.contains(&clear_title(&anime.title))
+        })
+        .map(|anime| anime.id)
+        .collect()
+}
+
+/// The downloaded file if there is one, the stream otherwise.
+pub fn episode_media(anime: &Anime, episode: &Episode) -> Result<Media, Box<dyn Error>> {
+    let title = format!("{} - {}", anime.title, episode.number);
+    let path = episode_file(anime, episode.number);
+    if path.is_file() {
+        return Ok(Media {
+            source: path.to_string_lossy().into_owned(),
+            title,
+            headers: Vec::new(),
+            user_agent: None,
+        });
+    }
+    stream_media(anime, episode)
+}
+
+/// The episode's stream with the headers the server wants, even if it has
+/// been downloaded.
+pub fn stream_media(anime: &Anime, episode: &Episode) -> Result<Media, Box<dyn Error>> {
+    let headers = construct_header()
+        .iter()
+        .filter(|(name, _)| **name != USER_AGENT && **name != CACHE_CONTROL)
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    Ok(Media {
+        source: decrypt_source_url(episode)?.to_string(),
+        title: format!("{} - {}", anime.title, episode.number),
+        headers,
+        user_agent: Some(USER_AGENT_VALUE.to_string()),
+    })
+}
+
+/// Downloads an episode, resuming a partial file. Transfers only run while
+/// the download schedule allows it and pause until the next window opens.
+/// Every run ends up in the download history, failed ones too. `queued`
+/// episodes wait behind this one, they count against the free disk space.
 pub async fn fetch_video(
     episode: &Episode,
     anime: &Anime,
+    queued: usize,
     mut sender: Sender<Message>,
 ) -> Result<(), Box<dyn Error>> {
-    let path = format!("./animes/{}", clear_title(&anime.title));
+    let begun = Instant::now();
+    let mut state = TransferState::default();
+    // Box<dyn Error> isn't Send, and the history is written across awaits.
+    let video_url = decrypt_source_url(episode).map_err(|e| e.to_string());
+    let source_hash = video_url.as_ref().map(hash_source).unwrap_or_default();
+    let mut busy = false;
+    let result = match video_url {
+        Ok(video_url) => download(&video_url, episode, anime, queued, &mut sender, &mut state)
+            .await
+            .map_err(|e| {
+                busy = is_busy(&*e);
+                e.to_string()
+            }),
+        Err(e) => Err(e),
+    };
+    // Someone else's download, it is theirs to record.
+    if busy {
+        let e = result.err().unwrap_or_default();
+        sender
+            .send(Message::Download(DownloadMessage::Skipped(e.clone())))
+            .await?;
+        return Err(e.into());
+    }
 
-    let path = Path::new(&path);
-    fs::create_dir_all(path)?; // Create folder if it don't exist.
-    let path = path.join(format!("{}.mp4", episode.number));
+    let active = state.active.as_secs_f64();
+    let entry = DownloadRecord {
+        anime: anime.id,
+        title: anime.title.clone(),
+        episode: episode.number,
+        source_hash,
+        size: episode_size(anime, episode.number),
+        duration_ms: begun.elapsed().as_millis() as u64,
+        speed: if active > 0.0 {
+            (state.bytes as f64 / active) as u64
+        } else {
+            0
+        },
+        finished_at: Local::now().naive_local(),
+        outcome: match &result {
+            Ok(()) => Outcome::Completed,
+            Err(e) => Outcome::Failed(e.clone()),
+        },
+    };
+    if let Err(e) = record(entry).map_err(|e| e.to_string()) {
+        sender
+            .send(Message::Notification(Text::raw(format!(
+                "Could not update the download history: {}",
+                e
+            ))))
+            .await?;
+    }
+
+    match result {
+        Ok(()) => {
+            sender
+                .send(Message::Download(DownloadMessage::Finished))
+                .await?
+        }
+        Err(e) => {
+            sender
+                .send(Message::Download(DownloadMessage::Failed(e.clone())))
+                .await?;
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// What a download did so far, over all its transfers.
+#[derive(Debug, Clone, Copy, Default)]
+struct TransferState {
+    started: bool,
+    bytes: u64,
+    /// Time spent receiving, pauses left out.
+    active: Duration,
+}
+
+/// Why a transfer stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stop {
+    Complete,
+    WindowClosed,
+    /// The server sent something other than a video, with what it was.
+    NotVideo(String),
+    /// The source is an HLS playlist, `transfer_hls` takes over.
+    Playlist,
+    /// The rest of the episode, or of the queue, won't fit on the disk.
+    NoSpace {
+        needed: u64,
+        free: u64,
+    },
+}
+
+/// Episodes waiting behind the one downloading.
+#[derive(Debug, Clone, Copy)]
+struct Queued {
+    episodes: usize,
+    /// Their likely size, see `DiskOptions::queued_bytes`.
+    bytes: u64,
+}
+
+async fn download(
+    video_url: &Url,
+    episode: &Episode,
+    anime: &Anime,
+    queued: usize,
+    sender: &mut Sender<Message>,
+    state: &mut TransferState,
+) -> Result<(), Box<dyn Error>> {
+    let config = Config::load()?;
+    let dir = anime_dir(anime);
+    fs::create_dir_all(&dir)?; // Create folder if it don't exist.
+
+    // The format is only known once the server answers, an unfinished
+    // download keeps the one it started with.
+    let path = episode_part(anime, episode.number)
+        .and_then(|part| target_path(&part))
+        .unwrap_or_else(|| episode_file(anime, episode.number));
+    let mut part = PartFile::open(&path, &hash_source(video_url))?;
+    // The history is only read once, it doesn't change while downloading.
+    let queued = Queued {
+        episodes: queued,
+        bytes: match queued {
+            0 => 0,
+            queued => config.disk.queued_bytes(queued),
+        },
+    };
+
+    loop {
+        if wait_for_window(&config.schedule, sender).await? {
+            sender
+                .send(Message::Download(DownloadMessage::Resumed))
+                .await?;
+        }
+        let since = Instant::now();
+        let stop = if part.info.hls.is_some() {
+            transfer_hls(video_url, &mut part, &dir, &config, sender, state).await
+        } else {
+            transfer(video_url, &mut part, &dir, &config, queued, sender, state).await
+        };
+        state.active += since.elapsed();
+        let stop = stop?;
+        match stop {
+            Stop::Complete => {
+                part.check_size()?;
+                let format = VideoFormat::from_path(part.target()).unwrap_or_default();
+                // A broken file can't be resumed into a working one.
+                let media = match format {
+                    VideoFormat::Mp4 => match validate(&part.path) {
+                        Ok(media) => media.to_string(),
+                        Err(Mp4Error::Io(e)) => return Err(e.into()),
+                        Err(e) => {
+                            part.discard()?;
+                            return Err(e.into());
+                        }
+                    },
+                    format => format.to_string(),
+                };
+                let path = part.finish()?;
+                let mut text = format!("{} episode {}: {}", anime.title, episode.number, media);
+                if config.metadata.tags && format == VideoFormat::Mp4 {
+                    if let Err(e) = tag_episode(&path, anime, episode.number) {
+                        text = format!("{}, could not write tags: {}", text, e);
+                    }
+                }
+                if config.metadata.nfo {
+                    if let Err(e) = write_nfos(anime) {
+                        text = format!("{}, could not write .nfo files: {}", text, e);
+                    }
+                }
+                sender.send(Message::Notification(Text::raw(text))).await?;
+                return Ok(());
+            }
+            Stop::WindowClosed => {}
+            Stop::Playlist => {
+                part.info.hls = Some(HlsProgress::default());
+                part.save_info()?;
+            }
+            Stop::NotVideo(what) => {
+                part.discard()?;
+                return Err(what.into());
+            }
+            Stop::NoSpace { needed, free } => {
+                sender
+                    .send(Message::Download(DownloadMessage::OutOfSpace(needed, free)))
+                    .await?;
+                wait_for_space(&config.disk, &dir, &part.file, needed).await?;
+                sender
+                    .send(Message::Download(DownloadMessage::Resumed))
+                    .await?;
+            }
+        }
+    }
+}
+
+/// Waits until `needed` bytes fit on the disk next to `file`.
+async fn wait_for_space(
+    disk: &DiskOptions,
+    dir: &Path,
+    file: &fs::File,
+    needed: u64,
+) -> Result<(), Box<dyn Error>> {
+    while disk.free_space(dir, file)? < needed {
+        Delay::new(SPACE_POLL).await;
+    }
+    Ok(())
+}
 
-    let mut file = OpenOptions::new().write(true).create(true).open(path)?;
+/// Waits until the schedule allows downloads, returns whether it had to.
+async fn wait_for_window(
+    schedule: &Schedule,
+    sender: &mut Sender<Message>,
+) -> Result<bool, Box<dyn Error>> {
+    let mut waited = false;
+    loop {
+        let now = Local::now().naive_local();
+        let opens = match schedule.status(now) {
+            Status::Closed { opens } => opens,
+            _ => return Ok(waited),
+        };
+        if !waited {
+            sender
+                .send(Message::Download(DownloadMessage::Paused(opens)))
+                .await?;
+            waited = true;
+        }
+        // Checked regularly rather than sleeping until `opens`, the clock may
+        // jump while the machine sleeps.
+        let wait = opens
+            .map(|opens| (opens - now).to_std().unwrap_or_default())
+            .unwrap_or(SCHEDULE_POLL)
+            .min(SCHEDULE_POLL);
+        Delay::new(wait.max(Duration::from_secs(1))).await;
+    }
+}
 
+/// Appends to the part file until the episode is complete or the download
+/// window closes. Nothing is written when the rest of the episode, or of the
+/// queue when it starts, won't fit.
+async fn transfer(
+    video_url: &Url,
+    part: &mut PartFile,
+    dir: &Path,
+    config: &Config,
+    queued: Queued,
+    sender: &mut Sender<Message>,
+    state: &mut TransferState,
+) -> Result<Stop, Box<dyn Error>> {
     let mut header = construct_header();
 
-    let file_size = file.seek(SeekFrom::End(0))?; // Find file size and set file pointer there.
+    let file = &mut part.file;
+    let mut file_size = file.seek(SeekFrom::End(0))?; // Find file size and set file pointer there.
     if file_size > 0 {
         // If resume, skip these bytes.
         let range = HeaderValue::from_str(&format!("bytes={}-", file_size))?;
-        header.append(reqwest::header::RANGE, range);
+        header.append(RANGE, range);
+        // Unless the file changed on the server since, then it is sent whole.
+        let info = &part.info;
+        let strong_etag = info.etag.as_ref().filter(|etag| !etag.starts_with("W/"));
+        if let Some(validator) = strong_etag.or(info.last_modified.as_ref()) {
+            header.append(IF_RANGE, HeaderValue::from_str(validator)?);
+        }
     }
 
-    let video_url = decrypt_source_url(episode)?;
     let mut response: Response = reqwest::Client::new()
-        .get(video_url)
+        .get(video_url.clone())
         .headers(header)
         .send()
         .await?;
 
+    let whole = match response.status() {
+        // Nothing left past the end of the file.
+        StatusCode::RANGE_NOT_SATISFIABLE if file_size > 0 => return Ok(Stop::Complete),
+        StatusCode::PARTIAL_CONTENT => false,
+        status if status.is_success() => {
+            if file_size > 0 {
+                // The range was ignored, start over.
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file_size = 0;
+            }
+            true
+        }
+        status => return Err(format!("Download failed: {}", status).into()),
+    };
+
+    // A fresh file is checked before anything is written to it, and named
+    // after what the server actually sends.
+    let mut first = None;
+    if file_size == 0 {
+        let chunk = response.chunk().await?;
+        let header_str = |name| response.headers().get(name)?.to_str().ok();
+        let probe = Probe {
+            content_type: header_str(CONTENT_TYPE),
+            content_disposition: header_str(CONTENT_DISPOSITION),
+            path: video_url.path(),
+            start: chunk.as_deref().unwrap_or_default(),
+        };
+        if is_playlist(&probe) {
+            return Ok(Stop::Playlist);
+        }
+        match detect(&probe) {
+            Ok(format) => retarget(part, format)?,
+            Err(what) => return Ok(Stop::NotVideo(what)),
+        }
+        first = chunk;
+    }
+
+    let header_value = |name| {
+        let value = response.headers().get(name)?.to_str().ok()?;
+        Some(value.to_string())
+    };
+    let (etag, last_modified) = (header_value(ETAG), header_value(LAST_MODIFIED));
+    let info = &mut part.info;
+    if whole {
+        info.expected_size = response.content_length();
+        info.etag = etag;
+        info.last_modified = last_modified;
+    } else {
+        let total = response.content_length().map(|length| length + file_size);
+        info.expected_size = info.expected_size.or(total);
+        info.etag = info.etag.take().or(etag);
+        info.last_modified = info.last_modified.take().or(last_modified);
+    }
+    part.save_info()?;
+    let file = &mut part.file;
+
     let content_length = match response.content_length() {
         Some(length) => length + file_size,
         None => {
             sender
-                .send(Message::Notification(tui::widgets::Text::raw(
+                .send(Message::Notification(Text::raw(
                     "Could not find how large the file would be :(",
                 )))
                 .await?;
@@ -238,25 +680,218 @@ pub async fn fetch_video(
         }
     };
 
+    if let Some(remaining) = response.content_length() {
+        let disk = &config.disk;
+        let free = disk.free_space(dir, file)?;
+        if remaining > free {
+            return Ok(Stop::NoSpace {
+                needed: remaining,
+                free,
+            });
+        }
+        let needed = remaining + queued.bytes;
+        if !state.started && needed > free {
+            let text = format!(
+                "Only {} free, this episode and the {} queued after it likely need {}",
+                convert(free),
+                queued.episodes,
+                convert(needed)
+            );
+            sender.send(Message::Notification(Text::raw(text))).await?;
+            return Ok(Stop::NoSpace { needed, free });
+        }
+        if disk.preallocate {
+            // Not every file system can, the check above is what matters.
+            let _ = preallocate(file, content_length);
+        }
+    }
+
     let mut fetched_so_far = file_size;
 
-    sender
-        .send(Message::Download(DownloadMessage::Starting))
-        .await?;
+    if !state.started {
+        sender
+            .send(Message::Download(DownloadMessage::Starting))
+            .await?;
+        state.started = true;
+    }
+
+    let mut throttle = Throttle::default();
 
-    while let Some(chunk) = response.chunk().await? {
+    loop {
+        let chunk = match first.take() {
+            Some(chunk) => chunk,
+            None => match response.chunk().await? {
+                Some(chunk) => chunk,
+                None => break,
+            },
+        };
         file.write_all(&chunk)?;
         fetched_so_far += chunk.len() as u64;
+        state.bytes += chunk.len() as u64;
         sender
             .send(Message::Download(DownloadMessage::Progress(
                 fetched_so_far,
                 content_length,
             )))
             .await?;
+
+        if !throttle.pass(&config.schedule, chunk.len() as u64).await {
+            return Ok(Stop::WindowClosed);
+        }
     }
-    sender
-        .send(Message::Download(DownloadMessage::Finished))
-        .await?;
 
+    Ok(Stop::Complete)
+}
+
+/// Like `transfer`, for a source that turned out to be an HLS playlist.
+/// Segments are fetched a few at a time and appended in order, the sidecar
+/// keeps how many made it so a later transfer continues after them.
+async fn transfer_hls(
+    playlist_url: &Url,
+    part: &mut PartFile,
+    dir: &Path,
+    config: &Config,
+    sender: &mut Sender<Message>,
+    state: &mut TransferState,
+) -> Result<Stop, Box<dyn Error>> {
+    let options = &config.hls;
+    let client = reqwest::Client::new();
+    let headers = construct_header();
+    let mut progress = part.info.hls.clone().unwrap_or_default();
+
+    let (segments, variant) = hls::resolve(
+        &client,
+        playlist_url,
+        &headers,
+        options,
+        progress.variant.as_deref(),
+    )
+    .await?;
+    if variant != progress.variant || progress.segments > segments.len() {
+        // Another stream than before, its segments don't line up.
+        progress = HlsProgress {
+            variant,
+            ..Default::default()
+        };
+    }
+    part.file.set_len(progress.bytes)?;
+    part.file.seek(SeekFrom::Start(progress.bytes))?;
+
+    let total = segments.len();
+    let first_path = segments[0].uri.path().to_string();
+    let remaining = &segments[progress.segments..];
+    let keys = hls::fetch_keys(&client, remaining, &headers, options.retries).await?;
+    let mut fetches = stream::iter(remaining.iter().cloned())
+        .map(|segment| {
+            let key = segment.key.as_ref().and_then(|key| keys.get(&key.uri));
+            hls::fetch_segment(&client, segment, key.copied(), &headers, options.retries)
+        })
+        .buffered(options.concurrency.max(1));
+
+    if !state.started {
+        sender
+            .send(Message::Download(DownloadMessage::Starting))
+            .await?;
+        state.started = true;
+    }
+
+    let mut throttle = Throttle::default();
+    while let Some(data) = fetches.next().await {
+        let data = data?;
+        if progress.segments == 0 {
+            let probe = Probe {
+                path: &first_path,
+                start: &data,
+                ..Default::default()
+            };
+            match detect(&probe) {
+                Ok(format) => retarget(part, format)?,
+                Err(what) => return Ok(Stop::NotVideo(what)),
+            }
+        }
+
+        let free = config.disk.free_space(dir, &part.file)?;
+        if data.len() as u64 > free {
+            let average = (progress.bytes + data.len() as u64) / (progress.segments as u64 + 1);
+            return Ok(Stop::NoSpace {
+                needed: average * (total - progress.segments) as u64,
+                free,
+            });
+        }
+        part.file.write_all(&data)?;
+        progress.segments += 1;
+        progress.bytes += data.len() as u64;
+        part.info.hls = Some(progress.clone());
+        part.save_info()?;
+        state.bytes += data.len() as u64;
+        sender
+            .send(Message::Download(DownloadMessage::Segments(
+                progress.segments,
+                total,
+                progress.bytes,
+            )))
+            .await?;
+
+        if !throttle.pass(&config.schedule, data.len() as u64).await {
+            return Ok(Stop::WindowClosed);
+        }
+    }
+
+    Ok(Stop::Complete)
+}
+
+/// Starts the part file over under the name `format` goes by, unless it
+/// already has it.
+fn retarget(part: &mut PartFile, format: VideoFormat) -> Result<(), Box<dyn Error>> {
+    if VideoFormat::from_path(part.target()) == Some(format) {
+        return Ok(());
+    }
+    let target = part.target().with_extension(format.extension());
+    let mut renamed = PartFile::open(&target, &part.info.source_hash)?;
+    renamed.file.set_len(0)?;
+    renamed.info.hls = part.info.hls.take();
+    mem::replace(part, renamed).discard()?;
     Ok(())
 }
+
+/// Keeps transfers to the speed limit of the current download window.
+#[derive(Debug, Clone, Copy)]
+struct Throttle {
+    limit: Option<u64>,
+    /// When the limit last changed, and the bytes received since.
+    since: (Instant, u64),
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            limit: None,
+            since: (Instant::now(), 0),
+        }
+    }
+}
+
+impl Throttle {
+    /// Accounts for `bytes` just received, waiting as long as the limit
+    /// asks. Returns false once the download window has closed.
+    async fn pass(&mut self, schedule: &Schedule, bytes: u64) -> bool {
+        let current = match schedule.status(Local::now().naive_local()) {
+            Status::Closed { .. } => return false,
+            Status::Open { limit, .. } => limit,
+            Status::Always => None,
+        };
+        if current != self.limit {
+            self.limit = current;
+            self.since = (Instant::now(), 0);
+        }
+        self.since.1 += bytes;
+        if let Some(limit) = self.limit {
+            let due = Duration::from_secs_f64(self.since.1 as f64 / limit.max(1) as f64);
+            let elapsed = self.since.0.elapsed();
+            if due > elapsed {
+                Delay::new(due - elapsed).await;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,577 @@
+use crate::{
+    api::{
+        anime_dir, downloaded_animes, downloaded_episodes, episode_file, fetch_all_animes,
+        fetch_anime, stream_media,
+    },
+    config::Config,
+    daemon,
+    downloads::{parse_since, DownloadHistory, Outcome},
+    follow::{fetch_episodes, Follows},
+    import::{find, resolve_title, Found, Pattern, Resolution},
+    library::{FileStatus, Library},
+    metadata::{refresh_nfos, tag_episode, write_nfos},
+    player::{timestamp, Media},
+    playlist::{Entry, PlaylistFormat},
+    pretty_bytes::convert,
+    query::{Filter, FilterContext, Query},
+    sanitize::sanitize_filename,
+    search::SearchIndex,
+    types::{Anime, Animes, ID},
+    watched::Watched,
+};
+use chrono::Local;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+static USAGE: &str = "Usage:
+    twist                   Start the interactive ui
+    twist search <query>    Print animes matching a search box query, e.g.
+                            twist search ongoing:yes eps:>12 shingeki
+    twist follow <anime>    Follow an anime, by id or title
+    twist unfollow <anime>  Stop following an anime, by id or title
+    twist follows           List followed animes and their new episodes
+    twist refresh           Check followed animes for new episodes
+    twist continue          Show the first unwatched episode of each followed anime
+    twist daemon            Keep downloading new episodes of followed animes,
+                            see \"daemon\" in config.json
+    twist import <dir> [--move] [--yes]
+                            Add episodes downloaded elsewhere to the library,
+                            --move puts them into ./animes, --yes skips
+                            titles that need confirming
+    twist downloads [--since <when>] [--failed] [<anime>]
+                            Print the download history, newest first. <when>
+                            is e.g. 12h, 7d, 2w or 2020-06-01
+    twist tag [<anime>]     Write show and episode tags into downloaded MP4
+                            files, of every anime or just the given one
+    twist nfo [<anime>]     Write Kodi/Jellyfin .nfo files for downloaded
+                            animes, or just the given one
+    twist playlist <anime> [--xspf] [--unwatched] [--remote] [--output <file>]
+                            Write an M3U8 (or XSPF) playlist of the downloaded
+                            episodes, --remote lists the streams instead, with
+                            the headers they need";
+
+/// Most animes `twist search` fetches the episodes of to check `eps:`.
+const MAX_COUNT_FETCHES: usize = 25;
+
+/// Runs a non interactive command, `args` excludes the program name.
+pub async fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args.first().map(String::as_str) {
+        Some("search") => search(&args[1..].join(" ")).await,
+        Some("follow") => follow(&args[1..].join(" ")).await,
+        Some("unfollow") => unfollow(&args[1..].join(" ")).await,
+        Some("follows") => follows(),
+        Some("refresh") => refresh().await,
+        Some("continue") => continue_watching(),
+        Some("daemon") => daemon::run().await,
+        Some("import") => import(&args[1..]).await,
+        Some("downloads") => downloads(&args[1..]),
+        Some("tag") => tag(&args[1..].join(" ")).await,
+        Some("nfo") => nfo(&args[1..].join(" ")).await,
+        Some("playlist") => playlist(&args[1..]).await,
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("Unknown command\n\n{}", USAGE).into()),
+    }
+}
+
+async fn search(input: &str) -> Result<(), Box<dyn Error>> {
+    let query = Query::parse(input)?;
+    let config = Config::load()?;
+    let follows = Follows::load().unwrap_or_default();
+    let mut animes = fetch_all_animes().await?;
+    // Followed animes know their episode count from the last check.
+    for anime in animes.iter_mut() {
+        if let Some(followed) = follows.get(anime.id) {
+            anime.episode_count = followed.anime.episode_count;
+        }
+    }
+
+    let context = FilterContext {
+        downloaded: downloaded_animes(&animes),
+        followed: follows.animes.iter().map(|f| f.anime.id).collect(),
+    };
+    // `eps:` waits until the episodes of the other matches are fetched.
+    let (counts, filters): (Vec<Filter>, Vec<Filter>) = query
+        .filters
+        .into_iter()
+        .partition(|filter| matches!(filter, Filter::Episodes(..)));
+    let query = Query { filters, ..query };
+    let index = SearchIndex::new(&animes);
+    let (_, mut matches) = index
+        .search(&query, &config.search, &context, None, || false)
+        .unwrap_or_default();
+
+    if !counts.is_empty() {
+        let unknown: Vec<Anime> = matches
+            .iter()
+            .filter(|m| m.anime.episode_count.is_none())
+            .map(|m| m.anime.clone())
+            .collect();
+        if unknown.len() > MAX_COUNT_FETCHES {
+            return Err(format!(
+                "eps: needs the episodes of {} animes, narrow the search down first",
+                unknown.len()
+            )
+            .into());
+        }
+        let fetched: HashMap<ID, usize> = fetch_episodes(unknown)
+            .await
+            .into_iter()
+            .filter_map(|(id, episodes)| Some((id, episodes.ok()?.len())))
+            .collect();
+        for m in matches.iter_mut() {
+            if let Some(count) = fetched.get(&m.anime.id) {
+                m.anime.episode_count = Some(*count);
+            }
+        }
+        matches.retain(|m| counts.iter().all(|f| f.matches(&m.anime, &context)));
+    }
+
+    for m in matches {
+        match &m.anime.alt_title {
+            Some(alt_title) => println!("{}\t{}\t({})", m.anime.id, m.anime.title, alt_title),
+            None => println!("{}\t{}", m.anime.id, m.anime.title),
+        }
+    }
+    Ok(())
+}
+
+async fn follow(input: &str) -> Result<(), Box<dyn Error>> {
+    let animes = fetch_all_animes().await?;
+    let anime = resolve(&animes, input)?;
+    let mut follows = Follows::load().unwrap_or_default();
+    if follows.is_followed(anime.id) {
+        println!("Already following {}", anime.title);
+        return Ok(());
+    }
+    follows.follow(anime);
+    follows.save()?;
+    println!("Following {}", anime.title);
+    Ok(())
+}
+
+async fn unfollow(input: &str) -> Result<(), Box<dyn Error>> {
+    let mut follows = Follows::load().unwrap_or_default();
+    let id = match input.trim().parse::<ID>() {
+        Ok(id) => id,
+        Err(_) => resolve(&fetch_all_animes().await?, input)?.id,
+    };
+    let title = follows.get(id).map(|f| f.anime.title.clone());
+    match title {
+        Some(title) if follows.unfollow(id) => {
+            follows.save()?;
+            println!("No longer following {}", title);
+            Ok(())
+        }
+        _ => Err(format!("Not following '{}'", input.trim()).into()),
+    }
+}
+
+fn follows() -> Result<(), Box<dyn Error>> {
+    let follows = Follows::load().unwrap_or_default();
+    for followed in &follows.animes {
+        let anime = &followed.anime;
+        match followed.new_episodes.len() {
+            0 => println!("{}\t{}", anime.id, anime.title),
+            new => println!("{}\t{}\t[{} new]", anime.id, anime.title, new),
+        }
+    }
+    Ok(())
+}
+
+fn continue_watching() -> Result<(), Box<dyn Error>> {
+    let follows = Follows::load().unwrap_or_default();
+    let watched = Watched::load().unwrap_or_default();
+    for followed in &follows.animes {
+        let anime = &followed.anime;
+        let numbers = followed.known_episodes.iter().copied();
+        if let Some(number) = watched.first_unwatched(anime.id, numbers) {
+            println!("{}\t{}\tepisode {}", anime.id, anime.title, number);
+        }
+    }
+    Ok(())
+}
+
+async fn refresh() -> Result<(), Box<dyn Error>> {
+    let mut follows = Follows::load().unwrap_or_default();
+    let animes = follows.animes.iter().map(|f| f.anime.clone()).collect();
+
+    for (id, episodes) in fetch_episodes(animes).await {
+        let title = follows.get(id).map(|f| f.anime.title.clone());
+        let title = title.unwrap_or_default();
+        match episodes {
+            Ok(episodes) => {
+                let new = follows.update(id, &episodes);
+                if !new.is_empty() {
+                    let numbers: Vec<String> = new.iter().map(|n| n.to_string()).collect();
+                    println!("{}\t{}\t{}", id, title, numbers.join(", "));
+                }
+            }
+            Err(e) => eprintln!("Could not check {}: {}", title, e),
+        }
+    }
+    follows.save()?;
+    Ok(())
+}
+
+/// Finds an anime by id, or the best title match otherwise.
+fn resolve<'a>(animes: &'a Animes, input: &str) -> Result<&'a Anime, Box<dyn Error>> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(format!("Expected an anime id or title\n\n{}", USAGE).into());
+    }
+    if let Ok(id) = input.parse::<ID>() {
+        return animes
+            .iter()
+            .find(|anime| anime.id == id)
+            .ok_or_else(|| format!("No anime with id {}", id).into());
+    }
+
+    let query = Query {
+        text: input.to_string(),
+        filters: Vec::new(),
+    };
+    let config = Config::load()?;
+    let index = SearchIndex::new(animes);
+    let (_, matches) = index
+        .search(
+            &query,
+            &config.search,
+            &FilterContext::default(),
+            None,
+            || false,
+        )
+        .unwrap_or_default();
+    let best = matches
+        .first()
+        .ok_or_else(|| format!("No anime matches '{}'", input))?;
+    animes
+        .iter()
+        .find(|anime| anime.id == best.anime.id)
+        .ok_or_else(|| format!("No anime matches '{}'", input).into())
+}
+
+async fn import(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let move_files = args.iter().any(|arg| arg == "--move");
+    let confirm = !args.iter().any(|arg| arg == "--yes");
+    let dir = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(dir) => PathBuf::from(dir),
+        None => return Err(format!("Expected a folder to import\n\n{}", USAGE).into()),
+    };
+
+    let config = Config::load()?;
+    let patterns = config
+        .import
+        .patterns
+        .iter()
+        .map(|pattern| Pattern::parse(pattern))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (found, unparsed) = find(&dir, &patterns)?;
+    for path in &unparsed {
+        eprintln!("No pattern fits {}", path.display());
+    }
+
+    let animes = fetch_all_animes().await?;
+    let index = SearchIndex::new(&animes);
+    let mut by_title: HashMap<String, Option<Anime>> = HashMap::new();
+    let mut library = Library::load().unwrap_or_default();
+    let mut imported = 0;
+
+    for file in found {
+        if !by_title.contains_key(&file.title) {
+            let anime = match resolve_title(&index, &file.title) {
+                Resolution::Matched(anime) => Some(anime),
+                Resolution::Ambiguous(candidates) if confirm => pick(&file.title, &candidates)?,
+                Resolution::Ambiguous(_) | Resolution::Unknown => None,
+            };
+            by_title.insert(file.title.clone(), anime);
+        }
+        let anime = match &by_title[&file.title] {
+            Some(anime) => anime,
+            None => {
+                eprintln!(
+                    "Skipped {}, no anime for '{}'",
+                    file.path.display(),
+                    file.title
+                );
+                continue;
+            }
+        };
+
+        let path = if move_files {
+            match move_into_layout(&file, anime) {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!("Could not move {}: {}", file.path.display(), e);
+                    continue;
+                }
+            }
+        } else {
+            fs::canonicalize(&file.path)?
+        };
+        library.record(path, anime.id, file.episode);
+        println!(
+            "{}\tepisode {}\t{}",
+            anime.title,
+            file.episode,
+            file.path.display()
+        );
+        imported += 1;
+    }
+
+    library.save()?;
+    println!("Imported {} file(s)", imported);
+    Ok(())
+}
+
+/// Lets the user choose among `candidates`, `None` skips the title.
+fn pick(title: &str, candidates: &[Anime]) -> Result<Option<Anime>, Box<dyn Error>> {
+    println!("Which anime is '{}'?", title);
+    for (i, anime) in candidates.iter().enumerate() {
+        match &anime.alt_title {
+            Some(alt_title) => println!("  {}) {} ({})", i + 1, anime.title, alt_title),
+            None => println!("  {}) {}", i + 1, anime.title),
+        }
+    }
+    loop {
+        print!("Number, or s to skip [1]: ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        match answer.trim() {
+            "" => return Ok(candidates.first().cloned()),
+            "s" | "S" => return Ok(None),
+            number => match number.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= candidates.len() => {
+                    return Ok(Some(candidates[n - 1].clone()))
+                }
+                _ => println!("Expected 1 to {} or s", candidates.len()),
+            },
+        }
+    }
+}
+
+/// Moves the file to where a download of the episode would end up.
+fn move_into_layout(file: &Found, anime: &Anime) -> io::Result<PathBuf> {
+    let extension = file
+        .path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let dir = anime_dir(anime);
+    fs::create_dir_all(&dir)?;
+    let target = dir.join(format!("{}.{}", file.episode, extension));
+    if target.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+    // Renaming fails across file systems.
+    if fs::rename(&file.path, &target).is_err() {
+        copy_then_remove(&file.path, &target)?;
+    }
+    Ok(target)
+}
+
+fn copy_then_remove(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+fn downloads(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut since = None;
+    let mut failed_only = false;
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--since" => {
+                let when = args
+                    .next()
+                    .ok_or_else(|| format!("Expected a time after --since\n\n{}", USAGE))?;
+                since = Some(parse_since(when, Local::now().naive_local())?);
+            }
+            "--failed" => failed_only = true,
+            word => words.push(word),
+        }
+    }
+    let anime = words.join(" ").to_lowercase();
+
+    let history = DownloadHistory::load()?;
+    let records: Vec<_> = history
+        .since(since)
+        .into_iter()
+        .filter(|r| !failed_only || !r.is_completed())
+        .filter(|r| anime.is_empty() || r.title.to_lowercase().contains(&anime))
+        .collect();
+
+    for record in &records {
+        let outcome = match &record.outcome {
+            Outcome::Completed => String::from("completed"),
+            Outcome::Failed(error) => format!("failed: {}", error),
+        };
+        println!(
+            "{}\t{}\tepisode {}\t{}\t{}\t{}/s\t{}",
+            record.finished_at.format("%Y-%m-%d %H:%M"),
+            record.title,
+            record.episode,
+            convert(record.size),
+            timestamp(record.duration_ms as f64 / 1000.0),
+            convert(record.speed),
+            outcome
+        );
+    }
+    let completed: Vec<_> = records.iter().filter(|r| r.is_completed()).collect();
+    println!(
+        "{} completed ({}), {} failed",
+        completed.len(),
+        convert(completed.iter().map(|r| r.size).sum()),
+        records.len() - completed.len()
+    );
+    Ok(())
+}
+
+async fn tag(input: &str) -> Result<(), Box<dyn Error>> {
+    let animes = fetch_all_animes().await?;
+    let only = match input.trim() {
+        "" => None,
+        input => Some(resolve(&animes, input)?.id),
+    };
+    let mut library = Library::load().unwrap_or_default();
+    library.scan(&animes)?;
+
+    let mut tagged = 0;
+    let mut failed = 0;
+    for file in &library.files {
+        let (id, episode) = match (file.anime, file.episode) {
+            (Some(id), Some(episode)) => (id, episode),
+            _ => continue,
+        };
+        let is_mp4 = matches!(file.path.extension(), Some(ext) if ext == "mp4");
+        if !is_mp4
+            || file.status() != FileStatus::Complete
+            || matches!(only, Some(only) if only != id)
+        {
+            continue;
+        }
+        let anime = match animes.iter().find(|anime| anime.id == id) {
+            Some(anime) => anime,
+            None => continue,
+        };
+        match tag_episode(&file.path, anime, episode) {
+            Ok(()) => {
+                println!("{}\tepisode {}\ttagged", anime.title, episode);
+                tagged += 1;
+            }
+            Err(e) => {
+                eprintln!("Could not tag {}: {}", file.path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    // The files changed, so the library has to look at them again.
+    library.scan(&animes)?;
+    library.save()?;
+    println!("Tagged {} file(s), {} failed", tagged, failed);
+    Ok(())
+}
+
+async fn nfo(input: &str) -> Result<(), Box<dyn Error>> {
+    let animes = fetch_all_animes().await?;
+    let written = match input.trim() {
+        "" => refresh_nfos(&animes)?,
+        input => write_nfos(resolve(&animes, input)?)?,
+    };
+    println!("Wrote {} .nfo file(s)", written);
+    Ok(())
+}
+
+async fn playlist(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut format = PlaylistFormat::default();
+    let mut unwatched_only = false;
+    let mut remote = false;
+    let mut output = None;
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--xspf" => format = PlaylistFormat::Xspf,
+            "--unwatched" => unwatched_only = true,
+            "--remote" => remote = true,
+            "--output" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| format!("Expected a file after --output\n\n{}", USAGE))?;
+                output = Some(PathBuf::from(path));
+            }
+            word => words.push(word),
+        }
+    }
+
+    let animes = fetch_all_animes().await?;
+    let anime = resolve(&animes, &words.join(" "))?;
+    let watched = Watched::load().unwrap_or_default();
+    let wanted = |number: i64| !unwatched_only || !watched.is_watched(anime.id, number);
+
+    let mut entries = Vec::new();
+    if remote {
+        let mut episodes = fetch_anime(anime).await?;
+        episodes.sort_by_key(|episode| episode.number);
+        for episode in episodes.iter().filter(|episode| wanted(episode.number)) {
+            entries.push(Entry {
+                number: episode.number,
+                media: stream_media(anime, episode)?,
+                duration: None,
+            });
+        }
+    } else {
+        let library = Library::load().unwrap_or_default();
+        for number in downloaded_episodes(anime)
+            .into_iter()
+            .filter(|n| wanted(*n))
+        {
+            // Absolute, so the playlist works from wherever it is saved.
+            let path = fs::canonicalize(episode_file(anime, number))?;
+            let duration = library
+                .files
+                .iter()
+                .find(|file| file.anime == Some(anime.id) && file.episode == Some(number))
+                .and_then(|file| file.media.as_ref()?.duration);
+            entries.push(Entry {
+                number,
+                media: Media {
+                    source: path.to_string_lossy().into_owned(),
+                    title: format!("{} - {}", anime.title, number),
+                    headers: Vec::new(),
+                    user_agent: None,
+                },
+                duration,
+            });
+        }
+    }
+    if entries.is_empty() {
+        return Err(format!("No episodes of {} to put in a playlist", anime.title).into());
+    }
+
+    let output = output.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "{}.{}",
+            sanitize_filename(&anime.title),
+            format.extension()
+        ))
+    });
+    fs::write(&output, format.render(&anime.title, &entries))?;
+    println!("Wrote {} episode(s) to {}", entries.len(), output.display());
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use crate::{
+    daemon::DaemonOptions, disk::DiskOptions, hls::HlsOptions, import::ImportOptions,
+    metadata::MetadataOptions, player::PlayerOptions, schedule::Schedule, search::SearchOptions,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::de;
+use std::{error::Error, fs, io, path::Path};
+
+pub static CONFIG_PATH: &str = "./config.json";
+
+/// User settings, every missing key falls back to its default.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub search: SearchOptions,
+    pub daemon: DaemonOptions,
+    pub schedule: Schedule,
+    pub player: PlayerOptions,
+    pub import: ImportOptions,
+    pub disk: DiskOptions,
+    pub metadata: MetadataOptions,
+    pub hls: HlsOptions,
+}
+
+impl Config {
+    /// The defaults when there is no config file. One that doesn't parse is
+    /// an error, a typo shouldn't silently reset every setting.
+    pub fn load() -> Result<Config, Box<dyn Error>> {
+        let s = match fs::read_to_string(Path::new(CONFIG_PATH)) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("Could not read {}: {}", CONFIG_PATH, e).into()),
+        };
+        de::from_str(&s).map_err(|e| format!("Could not read {}: {}", CONFIG_PATH, e).into())
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{
+    api::{downloaded_episodes, fetch_video},
+    config::Config,
+    follow::{fetch_episodes, Follows, RefreshResult},
+    pretty_bytes::convert,
+    types::{Anime, Episode},
+    ui::{DownloadMessage, Message},
+};
+use chrono::Local;
+use fs2::FileExt;
+use futures::{future::FutureExt, select};
+use futures_timer::Delay;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    process,
+    time::Duration,
+};
+use tokio::sync::mpsc::channel;
+use tui::widgets::Text;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DaemonOptions {
+    /// Seconds between two checks of the followed animes.
+    pub poll_interval: u64,
+    /// Up to this many seconds are added to every interval, so a fleet of
+    /// boxes doesn't hit the api at the same moment.
+    pub jitter: u64,
+    pub lock_file: String,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: 60 * 60,
+            jitter: 5 * 60,
+            lock_file: String::from("./.cache/daemon.lock"),
+        }
+    }
+}
+
+/// Holds the lock file for as long as it lives.
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+}
+
+impl Lock {
+    /// Locks the lock file and writes our pid in it. The system lets go of
+    /// the lock when its process ends, however it ends, so one left behind
+    /// by a daemon that is gone is simply taken over.
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Lock, Box<dyn Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Not truncated before it is locked, the pid belongs to whoever holds it.
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.try_lock_exclusive().is_err() {
+            // Only there to help whoever reads the message.
+            let pid = fs::read_to_string(path).unwrap_or_default();
+            return Err(format!(
+                "Another daemon is running (pid {}), stop it first",
+                pid.trim()
+            )
+            .into());
+        }
+        file.set_len(0)?;
+        write!(file, "{}", process::id())?;
+        Ok(Lock { file })
+    }
+}
+
+// The file stays, removing it would let a daemon that opened it just before
+// lock a file nobody else sees anymore.
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+fn log<T: Display>(message: T) {
+    println!("[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), message);
+}
+
+/// Downloads new episodes of the followed animes until interrupted.
+pub async fn run() -> Result<(), Box<dyn Error>> {
+    let options = Config::load()?.daemon;
+    let _lock = Lock::acquire(&options.lock_file)?;
+    log(format!("Daemon started, pid {}", process::id()));
+
+    let mut interrupted = tokio::signal::ctrl_c().boxed().fuse();
+    loop {
+        let wait = poll(&options).fuse();
+        futures::pin_mut!(wait);
+        select! {
+            result = wait => result?,
+            _ = interrupted => break,
+        }
+    }
+
+    log("Daemon stopped");
+    Ok(())
+}
+
+// A single check followed by the wait for the next one.
+async fn poll(options: &DaemonOptions) -> Result<(), Box<dyn Error>> {
+    if let Err(e) = check().await {
+        log(format!("Check failed: {}", e));
+    }
+
+    let jitter = match options.jitter {
+        0 => 0,
+        jitter => rand::thread_rng().gen_range(0, jitter + 1),
+    };
+    let wait = Duration::from_secs(options.poll_interval + jitter);
+    log(format!("Next check in {} minute(s)", wait.as_secs() / 60));
+    Delay::new(wait).await;
+    Ok(())
+}
+
+/// Refreshes the followed animes and downloads every episode that isn't on
+/// disk yet.
+pub async fn check() -> Result<(), Box<dyn Error>> {
+    let mut follows = Follows::load().unwrap_or_default();
+    if follows.animes.is_empty() {
+        log("Not following any animes");
+        return Ok(());
+    }
+    let animes: Vec<Anime> = follows.animes.iter().map(|f| f.anime.clone()).collect();
+    log(format!("Checking {} followed anime(s)", animes.len()));
+
+    let results = fetch_episodes(animes).await;
+    let queue = missing_episodes(&mut follows, results, downloaded_episodes);
+    follows.save()?;
+
+    let total = queue.len();
+    for (i, (anime, episode)) in queue.into_iter().enumerate() {
+        log(format!(
+            "Downloading {} episode {}",
+            anime.title, episode.number
+        ));
+        match download(&episode, &anime, total - i - 1).await {
+            Ok(()) => log(format!(
+                "Finished {} episode {}",
+                anime.title, episode.number
+            )),
+            Err(e) => log(format!(
+                "Failed {} episode {}: {}",
+                anime.title, episode.number, e
+            )),
+        }
+    }
+    Ok(())
+}
+
+/// Records the fetched episode lists and returns every episode that isn't on
+/// disk yet, so a newly followed anime gets all of its episodes.
+fn missing_episodes<F>(
+    follows: &mut Follows,
+    results: RefreshResult,
+    downloaded: F,
+) -> Vec<(Anime, Episode)>
+where
+    F: Fn(&Anime) -> Vec<i64>,
+{
+    let mut queue: Vec<(Anime, Episode)> = Vec::new();
+    for (id, episodes) in results {
+        let anime = match follows.get(id) {
+            Some(followed) => followed.anime.clone(),
+            None => continue,
+        };
+        let episodes = match episodes {
+            Ok(episodes) => episodes,
+            Err(e) => {
+                log(format!("Could not check {}: {}", anime.title, e));
+                continue;
+            }
+        };
+
+        let new = follows.update(id, &episodes);
+        if !new.is_empty() {
+            log(format!("{} new episode(s) of {}", new.len(), anime.title));
+        }
+        let downloaded = downloaded(&anime);
+        queue.extend(
+            episodes
+                .into_iter()
+                .filter(|episode| !downloaded.contains(&episode.number))
+                .map(|episode| (anime.clone(), episode)),
+        );
+    }
+    queue
+}
+
+// Runs the regular download, only logging what it would tell the ui.
+async fn download(episode: &Episode, anime: &Anime, queued: usize) -> Result<(), Box<dyn Error>> {
+    let (sender, mut receiver) = channel::<Message>(50);
+    let listener = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            match message {
+                Message::Notification(Text::Raw(text))
+                | Message::Notification(Text::Styled(text, _)) => log(text),
+                Message::Download(DownloadMessage::Paused(Some(opens))) => {
+                    log(format!("Paused until {}", opens.format("%a %H:%M")))
+                }
+                Message::Download(DownloadMessage::Paused(None)) => {
+                    log("Paused, no download window ahead")
+                }
+                Message::Download(DownloadMessage::Resumed) => log("Resumed"),
+                Message::Download(DownloadMessage::OutOfSpace(needed, free)) => log(format!(
+                    "Paused, needs {} but only {} are free",
+                    convert(needed),
+                    convert(free)
+                )),
+                // The error itself is logged by the caller.
+                Message::Download(DownloadMessage::Finished)
+                | Message::Download(DownloadMessage::Failed(_))
+                | Message::Download(DownloadMessage::Skipped(_)) => break,
+                _ => {}
+            }
+        }
+    });
+    let result = fetch_video(episode, anime, queued, sender).await;
+    let _ = listener.await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf, process};
+
+    fn lock_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("twist-daemon-{}-{}.lock", process::id(), name))
+    }
+
+    fn episodes(numbers: &[i64]) -> Vec<Episode> {
+        numbers
+            .iter()
+            .map(|&number| Episode {
+                number,
+                anime_id: 3,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn follows() -> Follows {
+        let mut follows = Follows::default();
+        follows.follow(&Anime {
+            id: 3,
+            title: String::from("Mushishi"),
+            ..Default::default()
+        });
+        follows
+    }
+
+    fn numbers(queue: &[(Anime, Episode)]) -> Vec<i64> {
+        queue.iter().map(|(_, episode)| episode.number).collect()
+    }
+
+    #[test]
+    fn only_one_daemon_holds_the_lock() {
+        let path = lock_path("held");
+        let lock = Lock::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            process::id().to_string()
+        );
+        let error = Lock::acquire(&path).unwrap_err().to_string();
+        assert!(error.contains(&process::id().to_string()), "{}", error);
+
+        drop(lock);
+        let lock = Lock::acquire(&path).unwrap();
+        drop(lock);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn locks_left_behind_are_taken_over() {
+        let path = lock_path("left");
+        fs::write(&path, "4194304").unwrap();
+        let lock = Lock::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            process::id().to_string()
+        );
+        drop(lock);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_first_check_queues_everything_missing() {
+        let mut follows = follows();
+        let results = vec![(3, Ok(episodes(&[1, 2, 3])))];
+        let queue = missing_episodes(&mut follows, results, |_| vec![2]);
+        assert_eq!(numbers(&queue), vec![1, 3]);
+
+        let followed = follows.get(3).unwrap();
+        assert_eq!(followed.known_episodes, vec![1, 2, 3]);
+        assert!(followed.new_episodes.is_empty());
+        assert!(followed.last_checked.is_some());
+    }
+
+    #[test]
+    fn later_checks_queue_new_episodes() {
+        let mut follows = follows();
+        missing_episodes(&mut follows, vec![(3, Ok(episodes(&[1, 2])))], |_| vec![]);
+        let results = vec![(3, Ok(episodes(&[1, 2, 3])))];
+        let queue = missing_episodes(&mut follows, results, |_| vec![1, 2]);
+        assert_eq!(numbers(&queue), vec![3]);
+        assert_eq!(follows.get(3).unwrap().new_episodes, vec![3]);
+    }
+
+    #[test]
+    fn failed_and_unfollowed_animes_are_left_out() {
+        let mut follows = follows();
+        let results = vec![(3, Err(String::from("timed out"))), (4, Ok(episodes(&[1])))];
+        let queue = missing_episodes(&mut follows, results, |_| vec![]);
+        assert!(queue.is_empty());
+        assert!(follows.get(3).unwrap().last_checked.is_none());
+    }
+}
@@ -3,13 +3,13 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{de, ser};
 
 use crate::{
-    api::{fetch_all_animes, fetch_anime},
+    api::{anime_folder, fetch_all_animes, fetch_anime},
     types::{Anime, Animes, Episode, Episodes, ID},
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     error::Error,
-    fs::{create_dir_all, read_to_string, write},
+    fs::{create_dir_all, read_to_string, rename, write},
     path::{Path, PathBuf},
     time::Duration,
 };
@@ -97,7 +97,7 @@ impl EpisodeStore {
                 NaiveDate::from_ymd(1970, 1, 1).and_time(NaiveTime::from_hms(1, 1, 1)),
                 Utc,
             ),
-            path: Path::new(&format!("./.cache/{}/episodes.json", anime.title)).to_path_buf(),
+            path: episodes_path(anime),
         }
     }
 
@@ -109,3 +109,122 @@ impl EpisodeStore {
         Ok(self.data.clone())
     }
 }
+
+/// Where the episodes of `anime` are cached. Caches from before folder
+/// names were sanitized, named after the raw title, are moved there.
+fn episodes_path(anime: &Anime) -> PathBuf {
+    let path = Path::new("./.cache/")
+        .join(anime_folder(anime))
+        .join("episodes.json");
+    let legacy = PathBuf::from(format!("./.cache/{}/episodes.json", anime.title));
+    if !path.exists() && legacy.is_file() {
+        if let Some(parent) = path.parent() {
+            let _ = create_dir_all(parent).and_then(|_| rename(&legacy, &path));
+        }
+    }
+    path
+}
+
+pub static HISTORY_PATH: &str = "./.cache/history.json";
+const MAX_QUERIES: usize = 50;
+const MAX_RECENT_ANIMES: usize = 10;
+
+/// Search queries and opened animes, newest first.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct History {
+    pub queries: VecDeque<String>,
+    pub animes: VecDeque<ID>,
+}
+
+impl History {
+    pub fn load() -> Result<History, Box<dyn Error>> {
+        let s = read_to_string(HISTORY_PATH)?;
+        let history: History = de::from_str(&s)?;
+        Ok(history)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(HISTORY_PATH);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, ser::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn push_query(&mut self, query: &str) {
+        let query = query.trim();
+        if query.is_empty() {
+            return;
+        }
+        self.queries.retain(|q| q != query);
+        self.queries.push_front(query.to_string());
+        self.queries.truncate(MAX_QUERIES);
+    }
+
+    pub fn push_anime(&mut self, id: ID) {
+        self.animes.retain(|a| *a != id);
+        self.animes.push_front(id);
+        self.animes.truncate(MAX_RECENT_ANIMES);
+    }
+
+    /// The recently opened animes that still exist, most recent first.
+    pub fn recent<'a>(&self, animes: &'a [Anime]) -> Vec<&'a Anime> {
+        self.animes
+            .iter()
+            .filter_map(|id| animes.iter().find(|anime| anime.id == *id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anime(id: ID) -> Anime {
+        Anime {
+            id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn queries_are_trimmed_and_moved_to_the_front() {
+        let mut history = History::default();
+        history.push_query("mushishi");
+        history.push_query("  kyojin ");
+        history.push_query("   ");
+        history.push_query("mushishi");
+        assert_eq!(history.queries, vec!["mushishi", "kyojin"]);
+    }
+
+    #[test]
+    fn only_the_latest_queries_are_kept() {
+        let mut history = History::default();
+        for i in 0..MAX_QUERIES + 5 {
+            history.push_query(&i.to_string());
+        }
+        assert_eq!(history.queries.len(), MAX_QUERIES);
+        assert_eq!(history.queries[0], (MAX_QUERIES + 4).to_string());
+        assert_eq!(history.queries[MAX_QUERIES - 1], "5");
+    }
+
+    #[test]
+    fn recent_animes_are_newest_first() {
+        let mut history = History::default();
+        for id in &[1, 2, 3, 1] {
+            history.push_anime(*id);
+        }
+        let animes = vec![anime(1), anime(2), anime(4)];
+        let recent: Vec<ID> = history.recent(&animes).iter().map(|a| a.id).collect();
+        // 3 is gone from the anime list.
+        assert_eq!(recent, vec![1, 2]);
+
+        for id in 10..10 + MAX_RECENT_ANIMES as ID {
+            history.push_anime(id);
+        }
+        assert_eq!(history.animes.len(), MAX_RECENT_ANIMES);
+        assert_eq!(history.animes[0], 9 + MAX_RECENT_ANIMES as ID);
+    }
+}
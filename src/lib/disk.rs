@@ -0,0 +1,178 @@
+use crate::downloads::{DownloadHistory, DownloadRecord};
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io, path::Path};
+
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DiskOptions {
+    /// MiB downloads leave free on the disk.
+    pub reserve: u64,
+    /// Reserve room for the whole episode before downloading it, where the
+    /// file system supports it.
+    pub preallocate: bool,
+    /// MiB assumed for each queued episode until some have been downloaded.
+    pub episode_estimate: u64,
+}
+
+impl Default for DiskOptions {
+    fn default() -> Self {
+        Self {
+            reserve: 1024,
+            preallocate: true,
+            episode_estimate: 400,
+        }
+    }
+}
+
+impl DiskOptions {
+    /// Bytes downloads may still use on the disk holding `dir`. Room already
+    /// preallocated for `file` counts as free.
+    pub fn free_space(&self, dir: &Path, file: &File) -> io::Result<u64> {
+        let available = fs2::available_space(dir)? + preallocated(file)?;
+        Ok(available.saturating_sub(self.reserve * MIB))
+    }
+
+    /// Likely size of `queued` more episodes, going by the past downloads.
+    pub fn queued_bytes(&self, queued: usize) -> u64 {
+        let records = DownloadHistory::load()
+            .map(|history| history.since(None))
+            .unwrap_or_default();
+        self.episode_size(&records) * queued as u64
+    }
+
+    /// Average size of the completed downloads among `records`.
+    fn episode_size(&self, records: &[DownloadRecord]) -> u64 {
+        let sizes: Vec<u64> = records
+            .iter()
+            .filter(|record| record.is_completed() && record.size > 0)
+            .map(|record| record.size)
+            .collect();
+        match sizes.len() {
+            0 => self.episode_estimate * MIB,
+            count => sizes.iter().sum::<u64>() / count as u64,
+        }
+    }
+}
+
+/// Allocates `len` bytes for `file` without changing its length, so resuming
+/// and the library still see how much was written.
+#[cfg(target_os = "linux")]
+pub fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_KEEP_SIZE,
+            0,
+            len as libc::off_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate(_file: &File, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+// Blocks allocated past the end of the file.
+#[cfg(unix)]
+fn preallocated(file: &File) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata()?;
+    Ok((metadata.blocks() * 512).saturating_sub(metadata.len()))
+}
+
+#[cfg(not(unix))]
+fn preallocated(_file: &File) -> io::Result<u64> {
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloads::Outcome;
+    use chrono::NaiveDate;
+    use std::{env, fs, process};
+
+    fn record(size: u64, outcome: Outcome) -> DownloadRecord {
+        DownloadRecord {
+            anime: 1,
+            title: String::from("Mushishi"),
+            episode: 1,
+            source_hash: String::new(),
+            size,
+            duration_ms: 0,
+            speed: 0,
+            finished_at: NaiveDate::from_ymd(2020, 6, 1).and_hms(0, 0, 0),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn episodes_are_as_large_as_completed_ones_were() {
+        let disk = DiskOptions::default();
+        let records = [
+            record(300 * MIB, Outcome::Completed),
+            record(500 * MIB, Outcome::Completed),
+            record(
+                10 * MIB,
+                Outcome::Failed(String::from("Download failed: 404")),
+            ),
+            record(0, Outcome::Completed),
+        ];
+        assert_eq!(disk.episode_size(&records), 400 * MIB);
+    }
+
+    #[test]
+    fn the_estimate_stands_in_without_downloads() {
+        let disk = DiskOptions {
+            episode_estimate: 250,
+            ..Default::default()
+        };
+        let failed = [record(10 * MIB, Outcome::Failed(String::new()))];
+        assert_eq!(disk.episode_size(&[]), 250 * MIB);
+        assert_eq!(disk.episode_size(&failed), 250 * MIB);
+    }
+
+    #[test]
+    fn the_reserve_is_kept_free() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("twist-disk-{}", process::id()));
+        let file = File::create(&path).unwrap();
+        let open = DiskOptions {
+            reserve: 0,
+            ..Default::default()
+        };
+        let full = DiskOptions {
+            reserve: u64::MAX / MIB,
+            ..Default::default()
+        };
+        let free = open.free_space(&dir, &file).unwrap();
+        let reserved = full.free_space(&dir, &file).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(free > 0);
+        assert_eq!(reserved, 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preallocated_room_counts_as_free() {
+        let path = env::temp_dir().join(format!("twist-prealloc-{}", process::id()));
+        let file = File::create(&path).unwrap();
+        // Not every file system can.
+        if preallocate(&file, 4 * MIB).is_ok() {
+            assert!(preallocated(&file).unwrap() >= 4 * MIB);
+            assert_eq!(file.metadata().unwrap().len(), 0);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
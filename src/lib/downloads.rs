@@ -0,0 +1,175 @@
+use crate::types::ID;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use fs2::FileExt;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    error::Error,
+    fs::{create_dir_all, OpenOptions},
+    path::PathBuf,
+};
+use tinydb::Database;
+use url::Url;
+
+pub static DOWNLOADS_PATH: &str = "./.cache/downloads.tinydb";
+/// tinydb replaces the database file on every save, so writers lock this one.
+static DOWNLOADS_LOCK_PATH: &str = "./.cache/downloads.tinydb.lock";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Outcome {
+    Completed,
+    /// With the error that stopped the download.
+    Failed(String),
+}
+
+/// One finished or failed run of `fetch_video`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct DownloadRecord {
+    pub anime: ID,
+    pub title: String,
+    pub episode: i64,
+    /// Md5 of the decrypted source url, tells apart re-uploads of an episode.
+    pub source_hash: String,
+    /// Size of the file once the download stopped.
+    pub size: u64,
+    /// Wall clock time, including time paused outside download windows.
+    pub duration_ms: u64,
+    /// Bytes per second while actually transferring.
+    pub speed: u64,
+    pub finished_at: NaiveDateTime,
+    pub outcome: Outcome,
+}
+
+impl DownloadRecord {
+    pub fn is_completed(&self) -> bool {
+        self.outcome == Outcome::Completed
+    }
+}
+
+pub fn hash_source(url: &Url) -> String {
+    format!("{:x}", Md5::digest(url.as_str().as_bytes()))
+}
+
+/// Every download ever recorded, kept in a tinydb file.
+#[derive(Debug, Clone)]
+pub struct DownloadHistory {
+    db: Database<DownloadRecord>,
+}
+
+impl DownloadHistory {
+    pub fn load() -> Result<DownloadHistory, Box<dyn Error>> {
+        let path = PathBuf::from(DOWNLOADS_PATH);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let db = Database::auto_from(path, false).map_err(|e| format!("{:?}", e))?;
+        Ok(DownloadHistory { db })
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        self.db.dump_db().map_err(|e| format!("{:?}", e))?;
+        Ok(())
+    }
+
+    pub fn add(&mut self, record: DownloadRecord) -> Result<(), Box<dyn Error>> {
+        self.db.add_item(record).map_err(|e| format!("{:?}", e))?;
+        Ok(())
+    }
+
+    /// Records finished at or after `since`, newest first.
+    pub fn since(&self, since: Option<NaiveDateTime>) -> Vec<DownloadRecord> {
+        let mut records: Vec<DownloadRecord> = self
+            .db
+            .read_db()
+            .iter()
+            .filter(|record| !matches!(since, Some(since) if record.finished_at < since))
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| Reverse(record.finished_at));
+        records
+    }
+}
+
+/// Adds `record` to the file. The history is read again first, the ui and
+/// the daemon may both be downloading, and stays locked until it is saved.
+pub fn record(record: DownloadRecord) -> Result<(), Box<dyn Error>> {
+    let path = PathBuf::from(DOWNLOADS_LOCK_PATH);
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    lock.lock_exclusive()?;
+
+    let result = DownloadHistory::load().and_then(|mut history| {
+        history.add(record)?;
+        history.save()
+    });
+    FileExt::unlock(&lock)?;
+    result
+}
+
+/// A point in time like `7d`, `12h`, `2w` ago, or a date like `2020-06-01`.
+pub fn parse_since(input: &str, now: NaiveDateTime) -> Result<NaiveDateTime, String> {
+    let input = input.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Ok(date.and_hms(0, 0, 0));
+    }
+    let error = || format!("Expected e.g. 12h, 7d, 2w or 2020-06-01, got '{}'", input);
+    let split = input.len().saturating_sub(1);
+    let amount: i64 = input
+        .get(..split)
+        .and_then(|amount| amount.parse().ok())
+        .ok_or_else(error)?;
+    let span = match &input[split..] {
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => return Err(error()),
+    };
+    Ok(now - span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd(2020, 6, 15).and_hms(12, 30, 0)
+    }
+
+    #[test]
+    fn spans_count_back_from_now() {
+        assert_eq!(
+            parse_since("12h", now()),
+            Ok(NaiveDate::from_ymd(2020, 6, 15).and_hms(0, 30, 0))
+        );
+        assert_eq!(
+            parse_since(" 7d ", now()),
+            Ok(NaiveDate::from_ymd(2020, 6, 8).and_hms(12, 30, 0))
+        );
+        assert_eq!(
+            parse_since("2w", now()),
+            Ok(NaiveDate::from_ymd(2020, 6, 1).and_hms(12, 30, 0))
+        );
+    }
+
+    #[test]
+    fn dates_start_at_midnight() {
+        assert_eq!(
+            parse_since("2020-06-01", now()),
+            Ok(NaiveDate::from_ymd(2020, 6, 1).and_hms(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn rejects_other_inputs() {
+        for input in &["", "d", "7", "7m", "x7d", "2020-13-01", "7д"] {
+            assert!(parse_since(input, now()).is_err(), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{
+    api::fetch_anime,
+    types::{Anime, Episodes, ID},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{de, ser};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+};
+
+pub static FOLLOWS_PATH: &str = "./.cache/follows.json";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Followed {
+    pub anime: Anime,
+    /// Episode numbers seen at the last refresh.
+    pub known_episodes: Vec<i64>,
+    /// Episodes that showed up since the anime was last opened.
+    pub new_episodes: Vec<i64>,
+    pub last_checked: Option<DateTime<Utc>>,
+}
+
+/// Animes we keep track of, with the episodes known for each.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Follows {
+    pub animes: Vec<Followed>,
+}
+
+impl Follows {
+    pub fn load() -> Result<Follows, Box<dyn Error>> {
+        let s = read_to_string(FOLLOWS_PATH)?;
+        let follows: Follows = de::from_str(&s)?;
+        Ok(follows)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(FOLLOWS_PATH);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, ser::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: ID) -> Option<&Followed> {
+        self.animes.iter().find(|f| f.anime.id == id)
+    }
+
+    pub fn is_followed(&self, id: ID) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn ids(&self) -> HashSet<ID> {
+        self.animes.iter().map(|f| f.anime.id).collect()
+    }
+
+    pub fn new_count(&self, id: ID) -> usize {
+        self.get(id).map_or(0, |f| f.new_episodes.len())
+    }
+
+    pub fn follow(&mut self, anime: &Anime) {
+        if self.is_followed(anime.id) {
+            return;
+        }
+        self.animes.push(Followed {
+            anime: anime.clone(),
+            known_episodes: Vec::new(),
+            new_episodes: Vec::new(),
+            last_checked: None,
+        });
+    }
+
+    pub fn unfollow(&mut self, id: ID) -> bool {
+        let before = self.animes.len();
+        self.animes.retain(|f| f.anime.id != id);
+        self.animes.len() != before
+    }
+
+    /// Returns whether the anime is followed afterwards.
+    pub fn toggle(&mut self, anime: &Anime) -> bool {
+        if self.unfollow(anime.id) {
+            false
+        } else {
+            self.follow(anime);
+            true
+        }
+    }
+
+    /// Stores the freshly fetched episode list and returns the episode numbers
+    /// that weren't known before. The first check only records a baseline.
+    pub fn update(&mut self, id: ID, episodes: &Episodes) -> Vec<i64> {
+        let followed = match self.animes.iter_mut().find(|f| f.anime.id == id) {
+            Some(followed) => followed,
+            None => return Vec::new(),
+        };
+
+        let mut numbers: Vec<i64> = episodes.iter().map(|e| e.number).collect();
+        numbers.sort();
+        numbers.dedup();
+
+        let new: Vec<i64> = match followed.last_checked {
+            Some(_) => numbers
+                .iter()
+                .filter(|n| !followed.known_episodes.contains(n))
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        followed.known_episodes = numbers;
+        followed.new_episodes.extend(new.iter().copied());
+        followed.anime.episode_count = Some(episodes.len());
+        followed.last_checked = Some(Utc::now());
+        new
+    }
+
+    /// What to tell about the episodes `update` found, if anything.
+    pub fn announcement(&self, id: ID, new: &[i64]) -> Option<String> {
+        if new.is_empty() {
+            return None;
+        }
+        let title = &self.get(id)?.anime.title;
+        let numbers: Vec<String> = new.iter().map(|n| n.to_string()).collect();
+        Some(format!(
+            "{} new episode(s) of {}: {}",
+            new.len(),
+            title,
+            numbers.join(", ")
+        ))
+    }
+
+    /// Clears the "new" badge, e.g. once the anime has been opened.
+    pub fn seen(&mut self, id: ID) {
+        if let Some(followed) = self.animes.iter_mut().find(|f| f.anime.id == id) {
+            followed.new_episodes.clear();
+        }
+    }
+}
+
+pub type RefreshResult = Vec<(ID, Result<Episodes, String>)>;
+
+/// Fetches the episode lists of the given animes one after another.
+pub async fn fetch_episodes(animes: Vec<Anime>) -> RefreshResult {
+    let mut results = Vec::with_capacity(animes.len());
+    for anime in animes {
+        let episodes = fetch_anime(&anime).await.map_err(|e| e.to_string());
+        results.push((anime.id, episodes));
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Episode;
+
+    fn anime(id: ID, title: &str) -> Anime {
+        Anime {
+            id,
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn episodes(numbers: &[i64]) -> Episodes {
+        numbers
+            .iter()
+            .map(|&number| Episode {
+                number,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn following(id: ID, title: &str) -> Follows {
+        let mut follows = Follows::default();
+        follows.follow(&anime(id, title));
+        follows
+    }
+
+    #[test]
+    fn the_first_update_is_a_baseline() {
+        let mut follows = following(3, "Mushishi");
+        let new = follows.update(3, &episodes(&[2, 1, 2]));
+        assert!(new.is_empty());
+
+        let followed = follows.get(3).unwrap();
+        assert_eq!(followed.known_episodes, vec![1, 2]);
+        assert!(followed.new_episodes.is_empty());
+        assert_eq!(followed.anime.episode_count, Some(3));
+        assert!(followed.last_checked.is_some());
+    }
+
+    #[test]
+    fn later_updates_find_new_episodes() {
+        let mut follows = following(3, "Mushishi");
+        follows.update(3, &episodes(&[1, 2]));
+        assert_eq!(follows.update(3, &episodes(&[1, 2, 3, 4])), vec![3, 4]);
+        assert!(follows.update(3, &episodes(&[1, 2, 3, 4])).is_empty());
+        assert_eq!(follows.new_count(3), 2);
+        assert!(follows.update(4, &episodes(&[1])).is_empty());
+    }
+
+    #[test]
+    fn new_episodes_are_announced() {
+        let mut follows = following(3, "Mushishi");
+        follows.update(3, &episodes(&[1]));
+        let new = follows.update(3, &episodes(&[1, 2, 3]));
+        assert_eq!(
+            follows.announcement(3, &new).unwrap(),
+            "2 new episode(s) of Mushishi: 2, 3"
+        );
+        assert_eq!(follows.announcement(3, &[]), None);
+        assert_eq!(follows.announcement(4, &new), None);
+    }
+
+    #[test]
+    fn seen_clears_the_new_episodes() {
+        let mut follows = following(3, "Mushishi");
+        follows.update(3, &episodes(&[1]));
+        follows.update(3, &episodes(&[1, 2]));
+        follows.seen(3);
+        let followed = follows.get(3).unwrap();
+        assert!(followed.new_episodes.is_empty());
+        assert_eq!(followed.known_episodes, vec![1, 2]);
+        assert!(follows.update(3, &episodes(&[1, 2])).is_empty());
+        assert_eq!(follows.new_count(3), 0);
+    }
+
+    #[test]
+    fn following_twice_keeps_one_entry() {
+        let mut follows = following(3, "Mushishi");
+        follows.follow(&anime(3, "Mushishi"));
+        assert_eq!(follows.animes.len(), 1);
+        assert!(!follows.toggle(&anime(3, "Mushishi")));
+        assert!(follows.toggle(&anime(3, "Mushishi")));
+        assert!(follows.is_followed(3));
+    }
+}
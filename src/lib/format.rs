@@ -0,0 +1,342 @@
+use std::{fmt, path::Path};
+
+/// Containers episodes are saved as, the file extension follows the format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VideoFormat {
+    #[default]
+    Mp4,
+    Matroska,
+    WebM,
+    MpegTs,
+    Flv,
+    Avi,
+}
+
+impl VideoFormat {
+    pub const ALL: &'static [VideoFormat] = &[
+        VideoFormat::Mp4,
+        VideoFormat::Matroska,
+        VideoFormat::WebM,
+        VideoFormat::MpegTs,
+        VideoFormat::Flv,
+        VideoFormat::Avi,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::Matroska => "mkv",
+            VideoFormat::WebM => "webm",
+            VideoFormat::MpegTs => "ts",
+            VideoFormat::Flv => "flv",
+            VideoFormat::Avi => "avi",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<VideoFormat> {
+        match extension.to_lowercase().as_str() {
+            "mp4" | "m4v" | "mov" => Some(VideoFormat::Mp4),
+            "mkv" => Some(VideoFormat::Matroska),
+            "webm" => Some(VideoFormat::WebM),
+            "ts" => Some(VideoFormat::MpegTs),
+            "flv" => Some(VideoFormat::Flv),
+            "avi" => Some(VideoFormat::Avi),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<VideoFormat> {
+        VideoFormat::from_extension(path.extension()?.to_str()?)
+    }
+
+    fn from_mime(mime: &str) -> Option<VideoFormat> {
+        match mime {
+            "video/mp4" | "video/quicktime" | "video/x-m4v" => Some(VideoFormat::Mp4),
+            "video/x-matroska" => Some(VideoFormat::Matroska),
+            "video/webm" => Some(VideoFormat::WebM),
+            "video/mp2t" => Some(VideoFormat::MpegTs),
+            "video/x-flv" => Some(VideoFormat::Flv),
+            "video/x-msvideo" | "video/avi" => Some(VideoFormat::Avi),
+            _ => None,
+        }
+    }
+
+    /// Recognizes the container from the first bytes of the file.
+    fn sniff(start: &[u8]) -> Option<VideoFormat> {
+        if start.get(4..8) == Some(b"ftyp") {
+            Some(VideoFormat::Mp4)
+        } else if start.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+            // The EBML header names the doctype early on.
+            let header = &start[..start.len().min(64)];
+            if header.windows(4).any(|window| window == b"webm") {
+                Some(VideoFormat::WebM)
+            } else {
+                Some(VideoFormat::Matroska)
+            }
+        } else if start.first() == Some(&0x47) && start.get(188) == Some(&0x47) {
+            Some(VideoFormat::MpegTs)
+        } else if start.starts_with(b"FLV") {
+            Some(VideoFormat::Flv)
+        } else if start.starts_with(b"RIFF") && start.get(8..12) == Some(b"AVI ") {
+            Some(VideoFormat::Avi)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for VideoFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// The headers and first bytes of a response, all a format is told from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Probe<'a> {
+    pub content_type: Option<&'a str>,
+    pub content_disposition: Option<&'a str>,
+    /// Path of the requested url.
+    pub path: &'a str,
+    pub start: &'a [u8],
+}
+
+/// Whether the response is an HLS playlist rather than the video itself.
+pub fn is_playlist(probe: &Probe) -> bool {
+    let mime = probe
+        .content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_lowercase())
+        .unwrap_or_default();
+    not_video(probe.start) == Some(NotVideo::Playlist)
+        || not_video_mime(&mime) == Some(NotVideo::Playlist)
+}
+
+/// The container of a download. Pages, JSON and playlists are rejected with
+/// what they seem to be, a format that can't be told falls back to MP4.
+pub fn detect(probe: &Probe) -> Result<VideoFormat, String> {
+    if let Some(format) = VideoFormat::sniff(probe.start) {
+        return Ok(format);
+    }
+    if let Some(what) = not_video(probe.start) {
+        return Err(format!("The server sent {} instead of a video", what));
+    }
+
+    let mime = probe
+        .content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_lowercase())
+        .unwrap_or_default();
+    if let Some(what) = not_video_mime(&mime) {
+        return Err(format!("The server sent {} instead of a video", what));
+    }
+
+    let hinted = probe
+        .content_disposition
+        .and_then(disposition_filename)
+        .and_then(|filename| VideoFormat::from_path(Path::new(&filename)))
+        .or_else(|| VideoFormat::from_mime(&mime))
+        .or_else(|| VideoFormat::from_path(Path::new(probe.path)));
+    Ok(hinted.unwrap_or_default())
+}
+
+/// What a response turned out to be instead of a video.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NotVideo {
+    Playlist,
+    Html,
+    Xml,
+    Json,
+    Text,
+}
+
+impl fmt::Display for NotVideo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self {
+            NotVideo::Playlist => "a playlist",
+            NotVideo::Html => "an HTML page",
+            NotVideo::Xml => "an XML document",
+            NotVideo::Json => "JSON",
+            NotVideo::Text => "text",
+        };
+        write!(f, "{}", what)
+    }
+}
+
+// What the start of a response looks like when it is clearly text.
+fn not_video(start: &[u8]) -> Option<NotVideo> {
+    let text = String::from_utf8_lossy(&start[..start.len().min(512)]);
+    let trimmed = text.trim_start_matches('\u{feff}').trim_start();
+    let lower = trimmed.to_lowercase();
+    if trimmed.starts_with("#EXTM3U") {
+        Some(NotVideo::Playlist)
+    } else if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        Some(NotVideo::Html)
+    } else if trimmed.starts_with('<') {
+        Some(NotVideo::Xml)
+    } else if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        Some(NotVideo::Json)
+    } else if !start.is_empty()
+        && start
+            .iter()
+            .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+    {
+        // Every container has binary in its header.
+        Some(NotVideo::Text)
+    } else {
+        None
+    }
+}
+
+fn not_video_mime(mime: &str) -> Option<NotVideo> {
+    match mime {
+        "text/html" | "application/xhtml+xml" => Some(NotVideo::Html),
+        "application/json" | "text/json" => Some(NotVideo::Json),
+        "application/vnd.apple.mpegurl" | "application/x-mpegurl" | "audio/mpegurl" => {
+            Some(NotVideo::Playlist)
+        }
+        mime if mime.starts_with("text/") => Some(NotVideo::Text),
+        _ => None,
+    }
+}
+
+/// The file name in a `Content-Disposition` header, `filename*` preferred.
+fn disposition_filename(header: &str) -> Option<String> {
+    let mut plain = None;
+    for param in header.split(';').skip(1) {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next()?.trim().to_lowercase();
+        let value = parts.next()?.trim();
+        match name.as_str() {
+            // `UTF-8''name.mkv`, the encoding doesn't matter for the extension.
+            "filename*" => return value.rsplit('\'').next().map(String::from),
+            "filename" => plain = Some(value.trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_containers_from_their_magic() {
+        let detect_start = |start: &[u8]| {
+            detect(&Probe {
+                start,
+                ..Default::default()
+            })
+        };
+        assert_eq!(
+            detect_start(b"\0\0\0\x20ftypisom\0\0\x02\0"),
+            Ok(VideoFormat::Mp4)
+        );
+        assert_eq!(
+            detect_start(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm"),
+            Ok(VideoFormat::WebM)
+        );
+        assert_eq!(
+            detect_start(b"\x1a\x45\xdf\xa3\xa3\x42\x86\x81\x01\x42\x82\x88matroska"),
+            Ok(VideoFormat::Matroska)
+        );
+        assert_eq!(detect_start(b"FLV\x01\x05"), Ok(VideoFormat::Flv));
+        assert_eq!(
+            detect_start(b"RIFF\x10\0\0\0AVI LIST"),
+            Ok(VideoFormat::Avi)
+        );
+        let mut ts = vec![0; 189];
+        ts[0] = 0x47;
+        ts[188] = 0x47;
+        assert_eq!(detect_start(&ts), Ok(VideoFormat::MpegTs));
+    }
+
+    #[test]
+    fn the_content_overrules_the_headers() {
+        let probe = Probe {
+            content_type: Some("video/mp4"),
+            start: b"<!DOCTYPE html><title>404</title>",
+            ..Default::default()
+        };
+        assert_eq!(
+            detect(&probe),
+            Err(String::from(
+                "The server sent an HTML page instead of a video"
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_what_the_mime_type_gives_away() {
+        let probe = Probe {
+            content_type: Some("application/json; charset=utf-8"),
+            start: b"\x00\x01",
+            ..Default::default()
+        };
+        assert_eq!(
+            detect(&probe),
+            Err(String::from("The server sent JSON instead of a video"))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_headers_then_the_url() {
+        let start: &[u8] = b"\x00\x01\x02";
+        let probe = Probe {
+            content_type: Some("application/octet-stream"),
+            content_disposition: Some("attachment; filename=\"3.mkv\""),
+            path: "/videos/3.mp4",
+            start,
+        };
+        assert_eq!(detect(&probe), Ok(VideoFormat::Matroska));
+        let probe = Probe {
+            content_type: Some("video/webm"),
+            path: "/videos/3.mp4",
+            start,
+            ..Default::default()
+        };
+        assert_eq!(detect(&probe), Ok(VideoFormat::WebM));
+        let probe = Probe {
+            path: "/videos/3.FLV",
+            start,
+            ..Default::default()
+        };
+        assert_eq!(detect(&probe), Ok(VideoFormat::Flv));
+        let probe = Probe {
+            path: "/videos/3",
+            start,
+            ..Default::default()
+        };
+        assert_eq!(detect(&probe), Ok(VideoFormat::Mp4));
+    }
+
+    #[test]
+    fn recognizes_playlists() {
+        let by_content = Probe {
+            start: b"\xef\xbb\xbf#EXTM3U\n#EXT-X-VERSION:3\n",
+            ..Default::default()
+        };
+        assert!(is_playlist(&by_content));
+        let by_type = Probe {
+            content_type: Some("Application/X-MpegURL"),
+            start: b"\x00\x01",
+            ..Default::default()
+        };
+        assert!(is_playlist(&by_type));
+        let page = Probe {
+            start: b"<html></html>",
+            ..Default::default()
+        };
+        assert!(!is_playlist(&page));
+    }
+
+    #[test]
+    fn disposition_prefers_the_encoded_filename() {
+        assert_eq!(
+            disposition_filename("attachment; filename=\"a.mp4\"; filename*=UTF-8''b.mkv"),
+            Some(String::from("b.mkv"))
+        );
+        assert_eq!(disposition_filename("inline"), None);
+    }
+}
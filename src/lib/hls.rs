@@ -0,0 +1,576 @@
+use aes::Aes128;
+use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
+use futures_timer::Delay;
+use reqwest::{header::HeaderMap, Client};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+use url::Url;
+
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+
+/// Wait before the first retry of a request, longer after each further one.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum VariantPreference {
+    #[default]
+    Highest,
+    Lowest,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HlsOptions {
+    /// Which stream of a master playlist is downloaded, by bandwidth.
+    pub variant: VariantPreference,
+    /// Streams taller than this are skipped while any other fits.
+    pub max_height: Option<u32>,
+    /// Segments downloaded at the same time.
+    pub concurrency: usize,
+    /// Further attempts at a segment or key before the download fails.
+    pub retries: u32,
+}
+
+impl Default for HlsOptions {
+    fn default() -> Self {
+        Self {
+            variant: VariantPreference::default(),
+            max_height: None,
+            concurrency: 4,
+            retries: 3,
+        }
+    }
+}
+
+/// How far a playlist download got, kept in the part file's sidecar.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HlsProgress {
+    /// Stream picked from the master playlist, resuming sticks with it.
+    pub variant: Option<String>,
+    /// Segments appended to the part file so far.
+    pub segments: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub uri: Url,
+    /// Bits per second.
+    pub bandwidth: u64,
+    pub height: Option<u32>,
+}
+
+/// An AES-128 key, media segments without an IV use their sequence number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    pub uri: Url,
+    pub iv: Option<[u8; 16]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub uri: Url,
+    /// Seconds.
+    pub duration: f64,
+    pub key: Option<Key>,
+    /// Media sequence number, the init section of fragmented MP4 segments
+    /// isn't one of them and has none.
+    pub sequence: Option<u64>,
+}
+
+impl Segment {
+    fn name(&self) -> String {
+        match self.sequence {
+            Some(sequence) => format!("segment {}", sequence),
+            None => String::from("the init section"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Playlist {
+    /// Streams of the same video in different qualities.
+    Master(Vec<Variant>),
+    Media(Vec<Segment>),
+}
+
+impl Playlist {
+    /// Reads a playlist fetched from `base`, which relative uris are
+    /// resolved against.
+    pub fn parse(text: &str, base: &Url) -> Result<Playlist, String> {
+        let mut lines = text
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(String::from("Not an HLS playlist"));
+        }
+        let resolve = |uri: &str| {
+            base.join(uri)
+                .map_err(|e| format!("Bad uri '{}' in playlist: {}", uri, e))
+        };
+
+        let mut variants = Vec::new();
+        let mut segments = Vec::new();
+        let mut stream = None;
+        let mut duration = 0.0;
+        let mut key = None;
+        let mut map = None;
+        let mut sequence = 0;
+        for line in lines {
+            if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+                let attributes = parse_attributes(attributes);
+                let bandwidth = attributes
+                    .get("BANDWIDTH")
+                    .and_then(|bandwidth| bandwidth.parse().ok())
+                    .unwrap_or(0);
+                let height = attributes
+                    .get("RESOLUTION")
+                    .and_then(|resolution| resolution.split('x').nth(1)?.parse().ok());
+                stream = Some((bandwidth, height));
+            } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                sequence = value
+                    .parse()
+                    .map_err(|_| format!("Bad media sequence '{}'", value))?;
+            } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+                let value = value.split(',').next().unwrap_or_default();
+                duration = value.trim().parse().unwrap_or(0.0);
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-KEY:") {
+                key = parse_key(&parse_attributes(attributes), base)?;
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+                // The init section of fragmented MP4 segments, written once
+                // before the segments that need it.
+                let uri = parse_attributes(attributes)
+                    .remove("URI")
+                    .ok_or("EXT-X-MAP without an uri")?;
+                let uri = resolve(&uri)?;
+                if map.as_ref() != Some(&uri) {
+                    segments.push(Segment {
+                        uri: uri.clone(),
+                        duration: 0.0,
+                        key: key.clone(),
+                        sequence: None,
+                    });
+                    map = Some(uri);
+                }
+            } else if line.starts_with("#EXT-X-BYTERANGE") {
+                return Err(String::from("Byte range segments are not supported"));
+            } else if line.starts_with('#') {
+                continue;
+            } else if let Some((bandwidth, height)) = stream.take() {
+                variants.push(Variant {
+                    uri: resolve(line)?,
+                    bandwidth,
+                    height,
+                });
+            } else {
+                segments.push(Segment {
+                    uri: resolve(line)?,
+                    duration,
+                    key: key.clone(),
+                    sequence: Some(sequence),
+                });
+                sequence += 1;
+                duration = 0.0;
+            }
+        }
+
+        if !variants.is_empty() {
+            Ok(Playlist::Master(variants))
+        } else if segments.is_empty() {
+            Err(String::from("The playlist has no segments"))
+        } else {
+            Ok(Playlist::Media(segments))
+        }
+    }
+}
+
+/// `KEY=value,OTHER="quoted, value"` into a map, quotes removed.
+fn parse_attributes(list: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = list.trim();
+    while !rest.is_empty() {
+        let (name, after) = match rest.find('=') {
+            Some(at) => (&rest[..at], &rest[at + 1..]),
+            None => break,
+        };
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let after = quoted.get(end + 1..).unwrap_or_default();
+                (&quoted[..end], after.trim_start_matches(','))
+            }
+            None => match after.find(',') {
+                Some(end) => (&after[..end], &after[end + 1..]),
+                None => (after, ""),
+            },
+        };
+        attributes.insert(name.trim().to_string(), value.to_string());
+        rest = after.trim_start();
+    }
+    attributes
+}
+
+fn parse_key(attributes: &HashMap<String, String>, base: &Url) -> Result<Option<Key>, String> {
+    let method = attributes.get("METHOD").map(String::as_str);
+    match method {
+        None | Some("NONE") => return Ok(None),
+        Some("AES-128") => {}
+        Some(method) => return Err(format!("{} encrypted segments are not supported", method)),
+    }
+    let uri = attributes.get("URI").ok_or("EXT-X-KEY without an uri")?;
+    let uri = base
+        .join(uri)
+        .map_err(|e| format!("Bad key uri '{}': {}", uri, e))?;
+    let iv = match attributes.get("IV") {
+        Some(iv) => Some(parse_iv(iv).ok_or_else(|| format!("Bad key IV '{}'", iv))?),
+        None => None,
+    };
+    Ok(Some(Key { uri, iv }))
+}
+
+// A 128 bit hex number, `0x` first.
+fn parse_iv(hex: &str) -> Option<[u8; 16]> {
+    let digits = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X"))?;
+    let padded = format!("{:0>32}", digits);
+    if padded.len() != 32 {
+        return None;
+    }
+    let mut iv = [0; 16];
+    for (i, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(padded.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(iv)
+}
+
+/// The variant to download, the preferred one among those that fit
+/// `max_height`, or the smallest when none does.
+pub fn choose_variant<'a>(variants: &'a [Variant], options: &HlsOptions) -> Option<&'a Variant> {
+    let fitting: Vec<&Variant> = variants
+        .iter()
+        .filter(|v| !matches!((options.max_height, v.height), (Some(max), Some(h)) if h > max))
+        .collect();
+    if fitting.is_empty() {
+        return variants.iter().min_by_key(|v| v.bandwidth);
+    }
+    match options.variant {
+        VariantPreference::Highest => fitting.into_iter().max_by_key(|v| v.bandwidth),
+        VariantPreference::Lowest => fitting.into_iter().min_by_key(|v| v.bandwidth),
+    }
+}
+
+/// Segments of the playlist at `url`, going through a master playlist to
+/// `previous` if it still lists it, or the preferred variant otherwise.
+/// Returns the variant picked too.
+pub async fn resolve(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    options: &HlsOptions,
+    previous: Option<&str>,
+) -> Result<(Vec<Segment>, Option<String>), String> {
+    let variants = match fetch_playlist(client, url, headers, options.retries).await? {
+        Playlist::Media(segments) => return Ok((segments, None)),
+        Playlist::Master(variants) => variants,
+    };
+    let variant = previous
+        .and_then(|previous| variants.iter().find(|v| v.uri.as_str() == previous))
+        .or_else(|| choose_variant(&variants, options))
+        .ok_or("The master playlist lists no streams")?;
+    match fetch_playlist(client, &variant.uri, headers, options.retries).await? {
+        Playlist::Media(segments) => Ok((segments, Some(variant.uri.to_string()))),
+        Playlist::Master(_) => Err(String::from("A master playlist points at another one")),
+    }
+}
+
+async fn fetch_playlist(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    retries: u32,
+) -> Result<Playlist, String> {
+    let data = fetch(client, url, headers, retries).await?;
+    Playlist::parse(&String::from_utf8_lossy(&data), url)
+}
+
+/// Fetches every key the segments need, once each.
+pub async fn fetch_keys(
+    client: &Client,
+    segments: &[Segment],
+    headers: &HeaderMap,
+    retries: u32,
+) -> Result<HashMap<Url, [u8; 16]>, String> {
+    let mut keys = HashMap::new();
+    for key in segments.iter().filter_map(|segment| segment.key.as_ref()) {
+        if keys.contains_key(&key.uri) {
+            continue;
+        }
+        let data = fetch(client, &key.uri, headers, retries).await?;
+        let mut bytes = [0; 16];
+        if data.len() != bytes.len() {
+            return Err(format!("The key at {} is {} bytes", key.uri, data.len()));
+        }
+        bytes.copy_from_slice(&data);
+        keys.insert(key.uri.clone(), bytes);
+    }
+    Ok(keys)
+}
+
+/// A segment's data, decrypted with `key` if it is encrypted.
+pub async fn fetch_segment(
+    client: &Client,
+    segment: Segment,
+    key: Option<[u8; 16]>,
+    headers: &HeaderMap,
+    retries: u32,
+) -> Result<Vec<u8>, String> {
+    let data = fetch(client, &segment.uri, headers, retries).await?;
+    match (&segment.key, key) {
+        (Some(_), Some(key)) => decrypt(&data, &key, &segment),
+        (Some(_), None) => Err(format!("No key for {}", segment.name())),
+        (None, _) => Ok(data),
+    }
+}
+
+fn decrypt(data: &[u8], key: &[u8; 16], segment: &Segment) -> Result<Vec<u8>, String> {
+    let explicit = segment.key.as_ref().and_then(|key| key.iv);
+    let iv = match (explicit, segment.sequence) {
+        (Some(iv), _) => iv,
+        (None, Some(sequence)) => sequence_iv(sequence),
+        // The playlist has to give the IV, there is no sequence number to
+        // fall back on.
+        (None, None) => return Err(format!("No IV to decrypt {} with", segment.name())),
+    };
+    Aes128Cbc::new_var(key, &iv)
+        .map_err(|e| e.to_string())?
+        .decrypt_vec(data)
+        .map_err(|e| format!("Could not decrypt {}: {}", segment.name(), e))
+}
+
+fn sequence_iv(sequence: u64) -> [u8; 16] {
+    let mut iv = [0; 16];
+    iv[8..].copy_from_slice(&sequence.to_be_bytes());
+    iv
+}
+
+/// The body at `url`, trying again up to `retries` times.
+async fn fetch(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    retries: u32,
+) -> Result<Vec<u8>, String> {
+    let mut attempt = 0;
+    loop {
+        match fetch_once(client, url, headers).await {
+            Ok(data) => return Ok(data),
+            Err(e) if attempt >= retries => {
+                return Err(format!("{}, gave up after {} attempts", e, attempt + 1))
+            }
+            Err(_) => {
+                attempt += 1;
+                Delay::new(RETRY_DELAY * attempt).await;
+            }
+        }
+    }
+}
+
+async fn fetch_once(client: &Client, url: &Url, headers: &HeaderMap) -> Result<Vec<u8>, String> {
+    let response = client
+        .get(url.clone())
+        .headers(headers.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{} for {}", status, url));
+    }
+    let data = response.bytes().await.map_err(|e| e.to_string())?;
+    Ok(data.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example.com/show/3/index.m3u8").unwrap()
+    }
+
+    #[test]
+    fn reads_master_playlists() {
+        let text = "#EXTM3U\n\
+                    #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\"\n\
+                    360p.m3u8\n\
+                    #EXT-X-STREAM-INF:BANDWIDTH=2800000,RESOLUTION=1280x720\n\
+                    https://other.example.com/720p.m3u8\n";
+        let variants = match Playlist::parse(text, &base()).unwrap() {
+            Playlist::Master(variants) => variants,
+            playlist => panic!("expected a master playlist, got {:?}", playlist),
+        };
+        assert_eq!(
+            variants,
+            vec![
+                Variant {
+                    uri: Url::parse("https://cdn.example.com/show/3/360p.m3u8").unwrap(),
+                    bandwidth: 800_000,
+                    height: Some(360),
+                },
+                Variant {
+                    uri: Url::parse("https://other.example.com/720p.m3u8").unwrap(),
+                    bandwidth: 2_800_000,
+                    height: Some(720),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_media_playlists() {
+        let text = "\u{feff}#EXTM3U\n\
+                    #EXT-X-TARGETDURATION:10\n\
+                    #EXT-X-MEDIA-SEQUENCE:7\n\
+                    #EXT-X-MAP:URI=\"init.mp4\"\n\
+                    #EXTINF:9.5,\n\
+                    seg7.m4s\n\
+                    \n\
+                    #EXT-X-KEY:METHOD=AES-128,URI=\"/keys/1\",IV=0x1\n\
+                    #EXTINF:4.25,title\n\
+                    seg8.m4s\n\
+                    #EXT-X-ENDLIST\n";
+        let segments = match Playlist::parse(text, &base()).unwrap() {
+            Playlist::Media(segments) => segments,
+            playlist => panic!("expected a media playlist, got {:?}", playlist),
+        };
+        let uris: Vec<&str> = segments.iter().map(|s| s.uri.path()).collect();
+        assert_eq!(
+            uris,
+            vec!["/show/3/init.mp4", "/show/3/seg7.m4s", "/show/3/seg8.m4s"]
+        );
+        let sequences: Vec<Option<u64>> = segments.iter().map(|s| s.sequence).collect();
+        assert_eq!(sequences, vec![None, Some(7), Some(8)]);
+        assert_eq!(segments[1].duration, 9.5);
+        assert_eq!(segments[2].duration, 4.25);
+        assert_eq!(segments[1].key, None);
+        let key = segments[2].key.as_ref().unwrap();
+        assert_eq!(key.uri.as_str(), "https://cdn.example.com/keys/1");
+        let mut iv = [0; 16];
+        iv[15] = 1;
+        assert_eq!(key.iv, Some(iv));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_download() {
+        let parse = |text: &str| Playlist::parse(text, &base()).unwrap_err();
+        assert_eq!(parse("<html>"), "Not an HLS playlist");
+        assert_eq!(
+            parse("#EXTM3U\n#EXT-X-ENDLIST\n"),
+            "The playlist has no segments"
+        );
+        assert_eq!(
+            parse("#EXTM3U\n#EXT-X-BYTERANGE:1000@0\nseg.ts\n"),
+            "Byte range segments are not supported"
+        );
+        assert_eq!(
+            parse("#EXTM3U\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"k\"\nseg.ts\n"),
+            "SAMPLE-AES encrypted segments are not supported"
+        );
+        assert_eq!(
+            parse("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:x\nseg.ts\n"),
+            "Bad media sequence 'x'"
+        );
+    }
+
+    #[test]
+    fn reads_ivs() {
+        assert_eq!(
+            parse_iv("0x000102030405060708090A0B0C0D0E0F"),
+            Some([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15])
+        );
+        assert_eq!(
+            parse_iv("0X0f"),
+            Some([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 15])
+        );
+        assert_eq!(parse_iv("000102"), None);
+        assert_eq!(parse_iv("0xzz"), None);
+        assert_eq!(parse_iv(&format!("0x{}", "1".repeat(33))), None);
+    }
+
+    fn encrypted(sequence: Option<u64>, iv: Option<[u8; 16]>) -> Segment {
+        Segment {
+            uri: base().join("seg.ts").unwrap(),
+            duration: 4.0,
+            key: Some(Key {
+                uri: base().join("key").unwrap(),
+                iv,
+            }),
+            sequence,
+        }
+    }
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+    const PLAIN: &[u8] = b"\x47 a transport stream packet";
+
+    fn encrypt(iv: [u8; 16]) -> Vec<u8> {
+        Aes128Cbc::new_var(&KEY, &iv).unwrap().encrypt_vec(PLAIN)
+    }
+
+    #[test]
+    fn sequence_numbers_fill_the_end_of_the_iv() {
+        assert_eq!(sequence_iv(0), [0; 16]);
+        assert_eq!(
+            sequence_iv(0x0102),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2]
+        );
+        assert_eq!(sequence_iv(u64::MAX)[..8], [0; 8]);
+    }
+
+    #[test]
+    fn decrypts_with_the_explicit_iv() {
+        let iv = *b"fedcba9876543210";
+        let segment = encrypted(Some(7), Some(iv));
+        assert_eq!(decrypt(&encrypt(iv), &KEY, &segment).unwrap(), PLAIN);
+    }
+
+    #[test]
+    fn decrypts_with_the_sequence_number_without_an_iv() {
+        let segment = encrypted(Some(7), None);
+        let data = encrypt(sequence_iv(7));
+        assert_eq!(decrypt(&data, &KEY, &segment).unwrap(), PLAIN);
+        // Any other IV garbles the first block or breaks the padding.
+        let wrong = encrypted(Some(8), None);
+        assert_ne!(decrypt(&data, &KEY, &wrong).ok().as_deref(), Some(PLAIN));
+    }
+
+    #[test]
+    fn encrypted_init_sections_need_their_own_iv() {
+        let iv = *b"fedcba9876543210";
+        let init = encrypted(None, Some(iv));
+        assert_eq!(decrypt(&encrypt(iv), &KEY, &init).unwrap(), PLAIN);
+        let init = encrypted(None, None);
+        assert_eq!(
+            decrypt(&encrypt(sequence_iv(0)), &KEY, &init).unwrap_err(),
+            "No IV to decrypt the init section with"
+        );
+    }
+
+    #[test]
+    fn init_sections_keep_the_key_but_take_no_sequence_number() {
+        let text = "#EXTM3U\n\
+                    #EXT-X-MEDIA-SEQUENCE:3\n\
+                    #EXT-X-KEY:METHOD=AES-128,URI=\"key\",IV=0x10\n\
+                    #EXT-X-MAP:URI=\"init.mp4\"\n\
+                    #EXTINF:4,\n\
+                    seg3.m4s\n";
+        let segments = match Playlist::parse(text, &base()).unwrap() {
+            Playlist::Media(segments) => segments,
+            playlist => panic!("expected a media playlist, got {:?}", playlist),
+        };
+        assert_eq!(segments[0].sequence, None);
+        assert_eq!(segments[0].key, segments[1].key);
+        assert_eq!(segments[1].sequence, Some(3));
+    }
+}
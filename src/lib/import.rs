@@ -0,0 +1,338 @@
+use crate::{
+    normalize::normalize_query,
+    query::{FilterContext, Query},
+    search::{SearchIndex, SearchOptions},
+    types::Anime,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+static VIDEO_EXTENSIONS: &[&str] = &["mp4", "mkv", "avi", "webm", "m4v"];
+
+/// Candidates offered when a title doesn't match an anime exactly.
+const MAX_CANDIDATES: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Filename layouts tried in order, see `Pattern`.
+    pub patterns: Vec<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        let patterns = [
+            "{title}/{episode}",
+            "[{_}] {title} - {episode}{_}",
+            "{title} - {episode}{_}",
+            "{title} Episode {episode}{_}",
+            "{title} E{episode}{_}",
+            "{title} {episode}{_}",
+        ];
+        Self {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Title,
+    Episode,
+    Any,
+}
+
+/// A filename layout like `[{_}] {title} - {episode}{_}`. `{title}` and
+/// `{episode}` capture the anime and the episode number, `{_}` skips anything.
+/// Every `/` matches one more parent folder, the extension is left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    parts: Vec<Part>,
+    depth: usize,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, String> {
+        let mut parts = Vec::new();
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            let (literal, placeholder) = match rest.find('{') {
+                Some(open) => (&rest[..open], Some(&rest[open..])),
+                None => (rest, None),
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(literal.to_string()));
+            }
+            rest = match placeholder {
+                Some(placeholder) => {
+                    let close = placeholder
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed '{{' in '{}'", pattern))?;
+                    parts.push(match &placeholder[1..close] {
+                        "title" => Part::Title,
+                        "episode" => Part::Episode,
+                        "_" => Part::Any,
+                        other => return Err(format!("unknown placeholder '{{{}}}'", other)),
+                    });
+                    &placeholder[close + 1..]
+                }
+                None => "",
+            };
+        }
+
+        for required in &[Part::Title, Part::Episode] {
+            if !parts.contains(required) {
+                return Err(format!("'{}' needs {{title}} and {{episode}}", pattern));
+            }
+        }
+        Ok(Pattern {
+            depth: pattern.matches('/').count(),
+            parts,
+        })
+    }
+
+    /// Title and episode number of the file at `path`.
+    pub fn matches(&self, path: &Path) -> Option<(String, i64)> {
+        let stem = path.with_extension("");
+        let components: Vec<_> = stem.components().collect();
+        if components.len() <= self.depth {
+            return None;
+        }
+        let input: Vec<String> = components[components.len() - self.depth - 1..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        // Underscores often stand in for spaces.
+        let input = input.join("/").replace('_', " ");
+        let mut captures = (None, None);
+        if !match_parts(&self.parts, &input, &mut captures) {
+            return None;
+        }
+        match captures {
+            (Some(title), Some(episode)) => Some((clean_title(&title), episode)),
+            _ => None,
+        }
+    }
+}
+
+// Tries the shortest title first and the longest episode number first,
+// backtracking until the whole input is used.
+fn match_parts(parts: &[Part], input: &str, captures: &mut (Option<String>, Option<i64>)) -> bool {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => return input.is_empty(),
+    };
+
+    match part {
+        Part::Literal(literal) => match input.get(..literal.len()) {
+            Some(start) if start.eq_ignore_ascii_case(literal) => {
+                match_parts(rest, &input[literal.len()..], captures)
+            }
+            _ => false,
+        },
+        Part::Episode => {
+            let digits = input.chars().take_while(|c| c.is_ascii_digit()).count();
+            (1..=digits).rev().any(|end| {
+                // "2nd Season" is not episode 2, "03v2" is episode 3.
+                let after = &input[end..];
+                let next = after.chars().next();
+                let version = after.starts_with('v')
+                    && matches!(after[1..].chars().next(), Some(c) if c.is_ascii_digit());
+                if matches!(next, Some(c) if c.is_alphanumeric()) && !version {
+                    return false;
+                }
+                match input[..end].parse() {
+                    Ok(number) if match_parts(rest, after, captures) => {
+                        captures.1 = Some(number);
+                        true
+                    }
+                    _ => false,
+                }
+            })
+        }
+        Part::Title | Part::Any => {
+            let min = if *part == Part::Title { 1 } else { 0 };
+            let ends = input
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(input.len()))
+                .filter(|end| *end >= min);
+            for end in ends {
+                if *part == Part::Title && input[..end].contains('/') {
+                    return false;
+                }
+                if match_parts(rest, &input[end..], captures) {
+                    if *part == Part::Title {
+                        captures.0 = Some(input[..end].to_string());
+                    }
+                    return true;
+                }
+            }
+            false
+        }
+    }
+}
+
+fn clean_title(title: &str) -> String {
+    let title = title.replace('.', " ");
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A video file whose name fits one of the patterns.
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub path: PathBuf,
+    pub title: String,
+    pub episode: i64,
+}
+
+/// Video files under `dir` that could be parsed, and the ones that couldn't.
+pub fn find(dir: &Path, patterns: &[Pattern]) -> io::Result<(Vec<Found>, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    walk(dir, &mut files)?;
+    files.sort();
+
+    let mut found = Vec::new();
+    let mut unparsed = Vec::new();
+    for path in files {
+        let relative = path.strip_prefix(dir).unwrap_or(&path);
+        match patterns.iter().find_map(|p| p.matches(relative)) {
+            Some((title, episode)) => found.push(Found {
+                path,
+                title,
+                episode,
+            }),
+            None => unparsed.push(path),
+        }
+    }
+    Ok((found, unparsed))
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+            continue;
+        }
+        if is_video(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file has one of the video extensions imports bring in.
+pub fn is_video(path: &Path) -> bool {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    matches!(extension, Some(ext) if VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+#[derive(Debug, Clone)]
+pub enum Resolution {
+    Matched(Anime),
+    /// Best guesses first, someone has to pick.
+    Ambiguous(Vec<Anime>),
+    Unknown,
+}
+
+/// Looks a parsed title up in the anime list. Only a title equal to an
+/// anime's title or alt title, once normalized, counts as a match.
+pub fn resolve_title(index: &SearchIndex, title: &str) -> Resolution {
+    let query = Query {
+        text: title.to_string(),
+        filters: Vec::new(),
+    };
+    let (_, matches) = index
+        .search(
+            &query,
+            &SearchOptions::default(),
+            &FilterContext::default(),
+            None,
+            || false,
+        )
+        .unwrap_or_default();
+
+    let wanted = normalize_query(title);
+    let exact = matches.iter().find(|m| {
+        let anime = &m.anime;
+        normalize_query(&anime.title) == wanted
+            || matches!(&anime.alt_title, Some(alt) if normalize_query(alt) == wanted)
+    });
+    match exact {
+        Some(m) => Resolution::Matched(m.anime.clone()),
+        None if matches.is_empty() => Resolution::Unknown,
+        None => Resolution::Ambiguous(
+            matches
+                .into_iter()
+                .take(MAX_CANDIDATES)
+                .map(|m| m.anime)
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert!(Pattern::parse("{title}").is_err());
+        assert!(Pattern::parse("{title} - {episode").is_err());
+        assert!(Pattern::parse("{title} - {season}").is_err());
+        assert!(Pattern::parse("{title} - {episode}").is_ok());
+    }
+
+    #[test]
+    fn matches_release_group_names() {
+        let pattern = Pattern::parse("[{_}] {title} - {episode}{_}").unwrap();
+        assert_eq!(
+            pattern.matches(Path::new(
+                "/downloads/[SubsPlease] Shingeki no Kyojin - 03 (1080p) [ABCD1234].mkv"
+            )),
+            Some((String::from("Shingeki no Kyojin"), 3))
+        );
+        assert_eq!(
+            pattern.matches(Path::new("Shingeki no Kyojin - 03.mkv")),
+            None
+        );
+    }
+
+    #[test]
+    fn folders_can_hold_the_title() {
+        let pattern = Pattern::parse("{title}/{episode}").unwrap();
+        assert_eq!(
+            pattern.matches(Path::new("/downloads/Made in Abyss/05.mp4")),
+            Some((String::from("Made in Abyss"), 5))
+        );
+        assert_eq!(pattern.matches(Path::new("05.mp4")), None);
+    }
+
+    #[test]
+    fn season_numbers_and_versions_are_not_episodes() {
+        let pattern = Pattern::parse("{title} {episode}{_}").unwrap();
+        assert_eq!(
+            pattern.matches(Path::new("Kaguya_sama_2nd_Season_03v2.mkv")),
+            Some((String::from("Kaguya sama 2nd Season"), 3))
+        );
+    }
+
+    #[test]
+    fn literals_ignore_case_and_dots_become_spaces() {
+        let pattern = Pattern::parse("{title} Episode {episode}{_}").unwrap();
+        assert_eq!(
+            pattern.matches(Path::new("Dr.Stone EPISODE 12.mp4")),
+            Some((String::from("Dr Stone"), 12))
+        );
+        assert_eq!(
+            pattern.matches(Path::new("Dr.Stone Episode twelve.mp4")),
+            None
+        );
+    }
+}
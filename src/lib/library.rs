@@ -0,0 +1,484 @@
+use crate::{
+    api::anime_folder,
+    format::VideoFormat,
+    metadata::is_nfo,
+    mp4::{validate, MediaInfo, Mp4Error},
+    partial::{is_part, is_sidecar, sidecar_path, target_path},
+    types::{clear_title, Anime, ID},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{de, ser};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, create_dir_all, read_to_string, write},
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+pub static LIBRARY_PATH: &str = "./.cache/library.json";
+pub static DOWNLOAD_ROOT: &str = "./animes";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Complete,
+    /// The download stopped before the end of the file, or left it broken.
+    Partial,
+    /// Not an episode of any known anime.
+    Orphaned,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct LibraryFile {
+    pub path: PathBuf,
+    pub anime: Option<ID>,
+    pub episode: Option<i64>,
+    pub size: u64,
+    /// Seconds since the epoch, together with `size` it tells whether the
+    /// file has to be looked at again.
+    pub modified: u64,
+    pub truncated: bool,
+    /// Duration and codecs of complete MP4 files.
+    pub media: Option<MediaInfo>,
+}
+
+impl LibraryFile {
+    pub fn status(&self) -> FileStatus {
+        if self.anime.is_none() || self.episode.is_none() {
+            FileStatus::Orphaned
+        } else if self.truncated {
+            FileStatus::Partial
+        } else {
+            FileStatus::Complete
+        }
+    }
+}
+
+/// Files of one anime, or of one folder when they can't be told apart.
+#[derive(Debug, Clone, Default)]
+pub struct Show {
+    pub folder: PathBuf,
+    pub anime: Option<Anime>,
+    pub files: Vec<LibraryFile>,
+}
+
+impl Show {
+    pub fn title(&self) -> String {
+        match &self.anime {
+            Some(anime) => anime.title.clone(),
+            None => self.folder.to_string_lossy().into_owned(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    pub fn count(&self, status: FileStatus) -> usize {
+        self.files.iter().filter(|f| f.status() == status).count()
+    }
+}
+
+/// Last scan of the download root, plus files imported from elsewhere.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Library {
+    pub files: Vec<LibraryFile>,
+}
+
+impl Library {
+    pub fn load() -> Result<Library, Box<dyn Error>> {
+        let s = read_to_string(LIBRARY_PATH)?;
+        let library: Library = de::from_str(&s)?;
+        Ok(library)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(LIBRARY_PATH);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, ser::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Walks the download root again. Only files that changed since the last
+    /// scan are opened, the rest keep their cached entry.
+    pub fn scan(&mut self, animes: &[Anime]) -> io::Result<()> {
+        self.scan_in(Path::new(DOWNLOAD_ROOT), animes)
+    }
+
+    fn scan_in(&mut self, root: &Path, animes: &[Anime]) -> io::Result<()> {
+        let folders = anime_folders(animes);
+        // Files imported from inside the root before their paths were
+        // normalized are keyed like the ones the walk finds.
+        let mut cached: HashMap<PathBuf, LibraryFile> = self
+            .files
+            .drain(..)
+            .map(|file| (library_path(root, &file.path), file))
+            .collect();
+
+        // Imported files outside the root stay as long as they exist.
+        let external: Vec<LibraryFile> = cached
+            .iter()
+            .filter(|(path, _)| !path.starts_with(root) && path.is_file())
+            .map(|(_, file)| file)
+            .cloned()
+            .collect();
+        for file in external {
+            let file = scan_file(file.path, file.anime, file.episode, &mut cached);
+            self.files.push(file);
+        }
+
+        if root.is_dir() {
+            self.scan_root(root, &folders, &mut cached)?;
+        }
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(())
+    }
+
+    fn scan_root(
+        &mut self,
+        root: &Path,
+        folders: &HashMap<String, ID>,
+        cached: &mut HashMap<PathBuf, LibraryFile>,
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            if is_sidecar(&path) || is_nfo(&path) {
+                continue;
+            }
+            if !path.is_dir() {
+                let (anime, episode) = identify(&path, None, cached);
+                self.files.push(scan_file(path, anime, episode, cached));
+                continue;
+            }
+            let anime = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| folders.get(name))
+                .copied();
+            // An unreadable folder leaves out its files, not the others.
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let path = entry.path();
+                if path.is_file() && !is_sidecar(&path) && !is_nfo(&path) {
+                    let (anime, episode) = identify(&path, anime, cached);
+                    self.files.push(scan_file(path, anime, episode, cached));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a file found somewhere else, or updates its entry.
+    pub fn record(&mut self, path: PathBuf, anime: ID, episode: i64) {
+        self.record_in(Path::new(DOWNLOAD_ROOT), path, anime, episode)
+    }
+
+    fn record_in(&mut self, root: &Path, path: PathBuf, anime: ID, episode: i64) {
+        let path = library_path(root, &path);
+        let file = scan_file(path, Some(anime), Some(episode), &mut HashMap::new());
+        self.files.retain(|f| f.path != file.path);
+        self.files.push(file);
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    /// Files grouped by folder, ordered by title.
+    pub fn shows(&self, animes: &[Anime]) -> Vec<Show> {
+        let mut shows: Vec<Show> = Vec::new();
+        for file in &self.files {
+            let folder = folder_name(&file.path);
+            let show = shows.iter_mut().find(|show| match file.anime {
+                Some(id) => matches!(&show.anime, Some(anime) if anime.id == id),
+                None => show.anime.is_none() && show.folder == folder,
+            });
+            match show {
+                Some(show) => show.files.push(file.clone()),
+                None => shows.push(Show {
+                    folder,
+                    anime: file
+                        .anime
+                        .and_then(|id| animes.iter().find(|anime| anime.id == id))
+                        .cloned(),
+                    files: vec![file.clone()],
+                }),
+            }
+        }
+        for show in &mut shows {
+            show.files.sort_by_key(|file| file.episode);
+        }
+        shows.sort_by_key(|show| show.title().to_lowercase());
+        shows
+    }
+
+    /// Deletes the file from disk, and its folder once it is empty.
+    pub fn remove(&mut self, path: &Path) -> io::Result<()> {
+        self.remove_in(Path::new(DOWNLOAD_ROOT), path)
+    }
+
+    fn remove_in(&mut self, root: &Path, path: &Path) -> io::Result<()> {
+        let mut paths = vec![path.to_path_buf()];
+        if is_part(path) {
+            paths.push(sidecar_path(path));
+        }
+        for path in paths {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        self.files.retain(|file| file.path != path);
+        if let Some(parent) = path.parent().filter(|p| p.starts_with(root) && *p != root) {
+            // Fails while the folder still has files, which is fine.
+            let _ = fs::remove_dir(parent);
+        }
+        Ok(())
+    }
+}
+
+fn folder_name(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if parent != Path::new(DOWNLOAD_ROOT) => {
+            parent.file_name().map(PathBuf::from).unwrap_or_default()
+        }
+        _ => PathBuf::new(),
+    }
+}
+
+/// Download folder names, including the ones from before sanitizing, per anime.
+fn anime_folders(animes: &[Anime]) -> HashMap<String, ID> {
+    let mut folders = HashMap::new();
+    for anime in animes {
+        folders.insert(clear_title(&anime.title), anime.id);
+        folders.insert(anime_folder(anime), anime.id);
+    }
+    folders
+}
+
+/// The path under `root` when the file is inside it, however it was given,
+/// so the walk and imports agree on it.
+fn library_path(root: &Path, path: &Path) -> PathBuf {
+    let inside = fs::canonicalize(root).and_then(|root| {
+        let path = fs::canonicalize(path)?;
+        path.strip_prefix(root)
+            .map(|rest| rest.to_path_buf())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    });
+    match inside {
+        Ok(rest) => root.join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+// Files imported into a folder that isn't an anime's, or under another name,
+// keep what the import said they are.
+fn identify(
+    path: &Path,
+    anime: Option<ID>,
+    cached: &HashMap<PathBuf, LibraryFile>,
+) -> (Option<ID>, Option<i64>) {
+    let imported = cached.get(path).filter(|file| file.anime.is_some());
+    (
+        anime.or_else(|| imported.and_then(|file| file.anime)),
+        episode_number(path).or_else(|| imported.and_then(|file| file.episode)),
+    )
+}
+
+// Files in the download root are named after the episode number, unfinished
+// ones have `.part` appended.
+fn episode_number(path: &Path) -> Option<i64> {
+    let path = target_path(path).unwrap_or_else(|| path.to_path_buf());
+    VideoFormat::from_path(&path)?;
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+// A file that can't be read counts as partial instead of failing the whole
+// scan. Without a modification time it is looked at again next scan.
+fn scan_file(
+    path: PathBuf,
+    anime: Option<ID>,
+    episode: Option<i64>,
+    cached: &mut HashMap<PathBuf, LibraryFile>,
+) -> LibraryFile {
+    let unreadable = LibraryFile {
+        path: path.clone(),
+        anime,
+        episode,
+        truncated: true,
+        ..Default::default()
+    };
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => return unreadable,
+    };
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs());
+
+    let is_mp4 = matches!(path.extension(), Some(ext) if ext == "mp4");
+    let (truncated, media) = match cached.remove(&path) {
+        _ if is_part(&path) => (true, None),
+        // Entries from before media info was kept are checked again.
+        Some(file)
+            if file.size == size
+                && file.modified == modified
+                && (file.truncated || file.media.is_some() || !is_mp4) =>
+        {
+            (file.truncated, file.media)
+        }
+        _ if episode.is_some() && is_mp4 => match validate(&path) {
+            Ok(media) => (false, Some(media)),
+            Err(Mp4Error::Io(_)) => return LibraryFile { size, ..unreadable },
+            Err(_) => (true, None),
+        },
+        _ => (false, None),
+    };
+    LibraryFile {
+        path,
+        anime,
+        episode,
+        size,
+        modified,
+        truncated,
+        media,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("twist-library-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn touch(path: &Path) {
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, b"not much of an episode").unwrap();
+    }
+
+    fn anime(id: ID, title: &str) -> Anime {
+        Anime {
+            id,
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn find<'a>(library: &'a Library, path: &Path) -> &'a LibraryFile {
+        library.files.iter().find(|file| file.path == path).unwrap()
+    }
+
+    #[test]
+    fn scans_anime_folders() {
+        let root = root("scan");
+        touch(&root.join("Mushishi/1.mkv"));
+        touch(&root.join("Mushishi/2.mp4"));
+        touch(&root.join("Unknown/3.mkv"));
+        touch(&root.join("notes.txt"));
+        let mut library = Library::default();
+        library.scan_in(&root, &[anime(3, "Mushishi")]).unwrap();
+
+        assert_eq!(library.files.len(), 4);
+        let first = find(&library, &root.join("Mushishi/1.mkv"));
+        assert_eq!((first.anime, first.episode), (Some(3), Some(1)));
+        assert_eq!(first.status(), FileStatus::Complete);
+        let broken = find(&library, &root.join("Mushishi/2.mp4"));
+        assert_eq!(broken.status(), FileStatus::Partial);
+        let unknown = find(&library, &root.join("Unknown/3.mkv"));
+        assert_eq!(unknown.status(), FileStatus::Orphaned);
+        assert_eq!(
+            find(&library, &root.join("notes.txt")).status(),
+            FileStatus::Orphaned
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn imports_inside_the_root_are_listed_once() {
+        let root = root("inside");
+        let path = root.join("Season One/Episode 05.mkv");
+        touch(&path);
+        let mut library = Library::default();
+        library.record_in(&root, fs::canonicalize(&path).unwrap(), 3, 5);
+        assert_eq!(library.files[0].path, path);
+
+        library.scan_in(&root, &[anime(3, "Mushishi")]).unwrap();
+        assert_eq!(library.files.len(), 1);
+        let file = &library.files[0];
+        assert_eq!((file.anime, file.episode), (Some(3), Some(5)));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn imports_from_before_normalizing_are_listed_once() {
+        let root = root("legacy");
+        let path = root.join("Mushishi/1.mkv");
+        touch(&path);
+        let mut library = Library {
+            files: vec![LibraryFile {
+                path: fs::canonicalize(&path).unwrap(),
+                anime: Some(3),
+                episode: Some(1),
+                ..Default::default()
+            }],
+        };
+        library.scan_in(&root, &[anime(3, "Mushishi")]).unwrap();
+        assert_eq!(library.files.len(), 1);
+        assert_eq!(library.files[0].path, path);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn imports_outside_the_root_stay_while_they_exist() {
+        let root = root("outside");
+        let elsewhere = self::root("elsewhere");
+        let path = elsewhere.join("Mushishi - 04.mkv");
+        touch(&path);
+        let mut library = Library::default();
+        library.record_in(&root, path.clone(), 3, 4);
+
+        library.scan_in(&root, &[]).unwrap();
+        let file = find(&library, &path);
+        assert_eq!((file.anime, file.episode), (Some(3), Some(4)));
+
+        fs::remove_file(&path).unwrap();
+        library.scan_in(&root, &[]).unwrap();
+        assert!(library.files.is_empty());
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(elsewhere).unwrap();
+    }
+
+    #[test]
+    fn removing_takes_the_sidecar_and_the_empty_folder() {
+        let root = root("remove");
+        let folder = root.join("Mushishi");
+        let part = folder.join("1.mp4.part");
+        touch(&part);
+        touch(&sidecar_path(&part));
+        touch(&folder.join("2.mkv"));
+        let mut library = Library::default();
+        library.scan_in(&root, &[anime(3, "Mushishi")]).unwrap();
+        assert_eq!(find(&library, &part).status(), FileStatus::Partial);
+
+        library.remove_in(&root, &part).unwrap();
+        assert!(!part.exists() && !sidecar_path(&part).exists());
+        assert_eq!(library.files.len(), 1);
+        library.remove_in(&root, &folder.join("2.mkv")).unwrap();
+        assert!(library.files.is_empty());
+        assert!(!folder.exists() && root.exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
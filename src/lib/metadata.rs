@@ -0,0 +1,175 @@
+use crate::{
+    api::{anime_dir, downloaded_animes, downloaded_episodes, episode_file},
+    mp4::{write_tags, Mp4Error, Tags},
+    types::Anime,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+pub static NFO_EXTENSION: &str = "nfo";
+pub static SHOW_NFO: &str = "tvshow.nfo";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MetadataOptions {
+    /// Write show and episode tags into finished downloads.
+    pub tags: bool,
+    /// Keep Kodi/Jellyfin `.nfo` files next to the downloads.
+    pub nfo: bool,
+}
+
+pub fn episode_tags(anime: &Anime, number: i64) -> Tags {
+    Tags {
+        title: format!("{} - Episode {}", anime.title, number),
+        show: anime.title.clone(),
+        episode: Some(number.max(0) as u32),
+        season: Some(anime.season).filter(|season| *season > 0),
+        description: anime
+            .alt_title
+            .as_ref()
+            .map(|alt_title| format!("Also known as {}.", alt_title)),
+    }
+}
+
+pub fn tag_episode(path: &Path, anime: &Anime, number: i64) -> Result<(), Mp4Error> {
+    write_tags(path, &episode_tags(anime, number))
+}
+
+pub fn is_nfo(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext == NFO_EXTENSION)
+}
+
+pub fn show_nfo(anime: &Anime) -> String {
+    let mut xml = String::from("<tvshow>\n");
+    element(&mut xml, "title", &anime.title);
+    if let Some(alt_title) = &anime.alt_title {
+        element(&mut xml, "originaltitle", alt_title);
+    }
+    element(
+        &mut xml,
+        "status",
+        if anime.ongoing { "Continuing" } else { "Ended" },
+    );
+    // The MAL id is what anime metadata providers match on.
+    if let Some(mal_id) = anime.mal_id {
+        unique_id(&mut xml, "mal", &mal_id.to_string(), true);
+    }
+    if let Some(hb_id) = anime.hb_id {
+        unique_id(
+            &mut xml,
+            "kitsu",
+            &hb_id.to_string(),
+            anime.mal_id.is_none(),
+        );
+    }
+    unique_id(&mut xml, "twist", &anime.slug(), false);
+    xml.push_str("</tvshow>\n");
+    document(xml)
+}
+
+pub fn episode_nfo(anime: &Anime, number: i64) -> String {
+    let mut xml = String::from("<episodedetails>\n");
+    element(&mut xml, "title", &format!("Episode {}", number));
+    element(&mut xml, "showtitle", &anime.title);
+    element(&mut xml, "season", &anime.season.max(1).to_string());
+    element(&mut xml, "episode", &number.to_string());
+    xml.push_str("</episodedetails>\n");
+    document(xml)
+}
+
+/// Writes `tvshow.nfo` and one `.nfo` per downloaded episode of `anime`.
+/// Files whose contents are already current are left alone, returns how
+/// many were written.
+pub fn write_nfos(anime: &Anime) -> io::Result<usize> {
+    let dir = anime_dir(anime);
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let mut written = 0;
+    if write_changed(&dir.join(SHOW_NFO), &show_nfo(anime))? {
+        written += 1;
+    }
+    for number in downloaded_episodes(anime) {
+        let path = episode_file(anime, number).with_extension(NFO_EXTENSION);
+        if write_changed(&path, &episode_nfo(anime, number))? {
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Brings the `.nfo` files of every downloaded anime up to date with `animes`.
+pub fn refresh_nfos(animes: &[Anime]) -> io::Result<usize> {
+    let downloaded = downloaded_animes(animes);
+    let mut written = 0;
+    for anime in animes.iter().filter(|anime| downloaded.contains(&anime.id)) {
+        written += write_nfos(anime)?;
+    }
+    Ok(written)
+}
+
+fn write_changed(path: &Path, contents: &str) -> io::Result<bool> {
+    if matches!(fs::read_to_string(path), Ok(current) if current == contents) {
+        return Ok(false);
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+fn document(body: String) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n{}",
+        body
+    )
+}
+
+fn element(xml: &mut String, name: &str, text: &str) {
+    xml.push_str(&format!("  <{0}>{1}</{0}>\n", name, escape(text)));
+}
+
+fn unique_id(xml: &mut String, kind: &str, id: &str, default: bool) {
+    xml.push_str(&format!(
+        "  <uniqueid type=\"{}\" default=\"{}\">{}</uniqueid>\n",
+        kind,
+        default,
+        escape(id)
+    ));
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters aren't allowed in XML 1.0.
+            c if c.is_control() && c != '\n' && c != '\t' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape("Tom & Jerry <\"Cat's\" Revenge>"),
+            "Tom &amp; Jerry &lt;&quot;Cat&apos;s&quot; Revenge&gt;"
+        );
+        assert_eq!(escape("&amp;"), "&amp;amp;");
+    }
+
+    #[test]
+    fn drops_control_characters_but_keeps_whitespace() {
+        assert_eq!(escape("a\u{0}b\u{1b}c"), "abc");
+        assert_eq!(escape("line\n\tindented"), "line\n\tindented");
+        assert_eq!(escape("Shōjo 少女"), "Shōjo 少女");
+    }
+}
@@ -1,6 +1,27 @@
 pub mod api;
+pub mod cli;
+pub mod config;
+pub mod daemon;
 pub mod datastore;
+pub mod disk;
+pub mod downloads;
+pub mod follow;
+pub mod format;
+pub mod hls;
+pub mod import;
+pub mod library;
+pub mod metadata;
+pub mod mp4;
+pub mod normalize;
+pub mod partial;
+pub mod player;
+pub mod playlist;
 pub mod pretty_bytes;
+pub mod query;
+pub mod sanitize;
+pub mod schedule;
+pub mod search;
 pub mod types;
 pub mod ui;
 pub mod ui_components;
+pub mod watched;
@@ -0,0 +1,724 @@
+use crate::{partial::sync_dir, player::timestamp};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, prelude::*, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+/// `moov` is read into memory, anything larger is not a real one.
+const MAX_MOOV_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Written next to a file while its tags are rewritten.
+const TAGGING_EXTENSION: &str = "tagging";
+
+// `data` box types.
+const TEXT_DATA: u32 = 1;
+const INT_DATA: u32 = 21;
+/// `stik` media kind for episodes of a show.
+const TV_SHOW: u8 = 10;
+
+/// Type and payload of a box inside `moov`.
+type Child<'a> = ([u8; 4], &'a [u8]);
+
+#[derive(Debug)]
+pub enum Mp4Error {
+    Io(io::Error),
+    /// Not an MP4 at all, with what it looks like instead.
+    NotMp4(String),
+    Invalid(String),
+}
+
+impl fmt::Display for Mp4Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mp4Error::Io(e) => write!(f, "{}", e),
+            Mp4Error::NotMp4(what) => write!(f, "not an MP4 file, {}", what),
+            Mp4Error::Invalid(message) => write!(f, "broken MP4 file, {}", message),
+        }
+    }
+}
+
+impl Error for Mp4Error {}
+
+impl From<io::Error> for Mp4Error {
+    fn from(e: io::Error) -> Self {
+        Mp4Error::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Track {
+    /// `vide`, `soun` and so on.
+    pub handler: String,
+    /// Sample entry type like `avc1` or `mp4a`.
+    pub codec: String,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct MediaInfo {
+    pub brand: String,
+    /// Seconds.
+    pub duration: Option<f64>,
+    pub tracks: Vec<Track>,
+}
+
+impl fmt::Display for MediaInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(duration) = self.duration {
+            parts.push(timestamp(duration));
+        }
+        for track in &self.tracks {
+            match (track.width, track.height) {
+                (Some(width), Some(height)) => {
+                    parts.push(format!("{} {}x{}", track.codec, width, height))
+                }
+                _ => parts.push(track.codec.clone()),
+            }
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Checks the file starts with `ftyp`, that the top level boxes fill it
+/// exactly, and that the samples `moov` points at lie inside an `mdat`.
+pub fn validate(path: &Path) -> Result<MediaInfo, Mp4Error> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut start = vec![0; len.min(64) as usize];
+    file.read_exact(&mut start)?;
+    if start.get(4..8) != Some(b"ftyp") {
+        return Err(Mp4Error::NotMp4(describe(&start)));
+    }
+
+    let mut info = MediaInfo::default();
+    let mut moov = None;
+    let mut mdats = Vec::new();
+    for top in top_level(&mut file, len)? {
+        match &top.kind {
+            b"ftyp" => {
+                let mut brand = [0; 4];
+                file.seek(SeekFrom::Start(top.payload().start))?;
+                file.read_exact(&mut brand)?;
+                info.brand = name(brand);
+            }
+            b"moov" => moov = Some(read_payload(&mut file, &top)?),
+            b"mdat" => mdats.push(top.payload()),
+            _ => {}
+        }
+    }
+
+    let moov = moov.ok_or_else(|| Mp4Error::Invalid(String::from("no 'moov' box")))?;
+    if mdats.is_empty() {
+        return Err(Mp4Error::Invalid(String::from("no 'mdat' box")));
+    }
+    read_moov(&moov, &mdats, &mut info)?;
+    Ok(info)
+}
+
+/// iTunes style metadata, as players read it from `moov/udta/meta/ilst`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tags {
+    pub title: String,
+    pub show: String,
+    pub episode: Option<u32>,
+    pub season: Option<u32>,
+    pub description: Option<String>,
+}
+
+impl Tags {
+    fn ilst(&self) -> Vec<u8> {
+        let mut items = Vec::new();
+        items.extend(text_item(b"\xa9nam", &self.title));
+        items.extend(text_item(b"\xa9alb", &self.show));
+        items.extend(text_item(b"tvsh", &self.show));
+        if let Some(episode) = self.episode {
+            items.extend(data_item(b"tves", INT_DATA, &episode.to_be_bytes()));
+            items.extend(data_item(b"trkn", 0, &track_number(episode)));
+        }
+        if let Some(season) = self.season {
+            items.extend(data_item(b"tvsn", INT_DATA, &season.to_be_bytes()));
+        }
+        items.extend(data_item(b"stik", INT_DATA, &[TV_SHOW]));
+        if let Some(description) = &self.description {
+            items.extend(text_item(b"desc", description));
+            items.extend(text_item(b"ldes", description));
+        }
+        mp4_box(b"ilst", &items)
+    }
+}
+
+/// Replaces the metadata of the MP4 at `path`. Only `moov` is rebuilt, the
+/// rest is copied as is into a new file that then replaces the old one, so
+/// an interrupted run leaves the original untouched.
+pub fn write_tags(path: &Path, tags: &Tags) -> Result<(), Mp4Error> {
+    validate(path)?;
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let boxes = top_level(&mut file, len)?;
+    let old = match boxes.iter().find(|top| &top.kind == b"moov") {
+        Some(moov) => *moov,
+        None => return Err(Mp4Error::Invalid(String::from("no 'moov' box"))),
+    };
+    let mut moov = with_tags(&read_payload(&mut file, &old)?, tags)?;
+
+    // Samples behind `moov` move along with everything else after it.
+    let delta = moov.len() as i64 - old.size as i64;
+    if delta != 0 {
+        shift_offsets(&mut moov[8..], old.offset + old.size, delta)?;
+    }
+
+    let temp = path.with_extension(TAGGING_EXTENSION);
+    if let Err(e) = copy_with(&mut file, &boxes, &old, &moov, &temp) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    fs::rename(&temp, path)?;
+    sync_dir(path);
+    Ok(())
+}
+
+/// Where a top level box sits in the file.
+#[derive(Debug, Clone, Copy)]
+struct TopBox {
+    kind: [u8; 4],
+    offset: u64,
+    size: u64,
+    header_len: u64,
+}
+
+impl TopBox {
+    fn payload(&self) -> Range<u64> {
+        self.offset + self.header_len..self.offset + self.size
+    }
+}
+
+/// The boxes that make up the file, which must fill it exactly.
+fn top_level(file: &mut File, len: u64) -> Result<Vec<TopBox>, Mp4Error> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let left = len - offset;
+        let mut header = vec![0; left.min(16) as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let (kind, size, header_len) = parse_header(&header, left)?;
+        if size > left {
+            return Err(Mp4Error::Invalid(format!(
+                "'{}' box needs {} more bytes than the file has",
+                name(kind),
+                size - left
+            )));
+        }
+        if &kind == b"moov" && size > MAX_MOOV_SIZE {
+            return Err(Mp4Error::Invalid(format!("'moov' box is {} bytes", size)));
+        }
+        boxes.push(TopBox {
+            kind,
+            offset,
+            size,
+            header_len,
+        });
+        offset += size;
+    }
+    Ok(boxes)
+}
+
+fn read_payload(file: &mut File, top: &TopBox) -> Result<Vec<u8>, Mp4Error> {
+    let mut payload = vec![0; (top.size - top.header_len) as usize];
+    file.seek(SeekFrom::Start(top.payload().start))?;
+    file.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+fn read_moov(moov: &[u8], mdats: &[Range<u64>], info: &mut MediaInfo) -> Result<(), Mp4Error> {
+    let boxes = children(moov, "moov")?;
+    let mvhd = find(&boxes, b"mvhd").ok_or_else(|| missing("mvhd", "moov"))?;
+    info.duration = duration(mvhd);
+
+    for (_, trak) in boxes.iter().filter(|(kind, _)| kind == b"trak") {
+        let mdia =
+            find(&children(trak, "trak")?, b"mdia").ok_or_else(|| missing("mdia", "trak"))?;
+        let mdia = children(mdia, "mdia")?;
+        let handler = find(&mdia, b"hdlr")
+            .and_then(|hdlr| hdlr.get(8..12))
+            .map(|kind| name([kind[0], kind[1], kind[2], kind[3]]))
+            .unwrap_or_default();
+        let minf = find(&mdia, b"minf").ok_or_else(|| missing("minf", "mdia"))?;
+        let stbl =
+            find(&children(minf, "minf")?, b"stbl").ok_or_else(|| missing("stbl", "minf"))?;
+        let stbl = children(stbl, "stbl")?;
+
+        for offset in chunk_offsets(&stbl) {
+            if !mdats.iter().any(|mdat| mdat.contains(&offset)) {
+                return Err(Mp4Error::Invalid(format!(
+                    "'{}' samples at byte {} are outside every 'mdat' box",
+                    handler, offset
+                )));
+            }
+        }
+
+        // The first sample entry, after version, flags and the entry count.
+        let entry = find(&stbl, b"stsd").and_then(|stsd| stsd.get(8..));
+        let codec = entry.and_then(|entry| entry.get(4..8));
+        let mut track = Track {
+            codec: codec
+                .map(|kind| name([kind[0], kind[1], kind[2], kind[3]]))
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        if handler == "vide" {
+            track.width = entry.and_then(|entry| be_u16(entry.get(32..34)?));
+            track.height = entry.and_then(|entry| be_u16(entry.get(34..36)?));
+        }
+        track.handler = handler;
+        info.tracks.push(track);
+    }
+    Ok(())
+}
+
+fn duration(mvhd: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (
+            be_u32(mvhd.get(12..16)?)?,
+            be_u32(mvhd.get(16..20)?)? as u64,
+        ),
+        _ => (be_u32(mvhd.get(20..24)?)?, be_u64(mvhd.get(24..32)?)?),
+    };
+    if timescale == 0 {
+        return None;
+    }
+    Some(duration as f64 / timescale as f64)
+}
+
+fn chunk_offsets(stbl: &[Child]) -> Vec<u64> {
+    let (table, width) = match (find(stbl, b"stco"), find(stbl, b"co64")) {
+        (Some(stco), _) => (stco, 4),
+        (None, Some(co64)) => (co64, 8),
+        (None, None) => return Vec::new(),
+    };
+    let count = table.get(4..8).and_then(be_u32).unwrap_or(0) as usize;
+    table
+        .get(8..)
+        .unwrap_or_default()
+        .chunks_exact(width)
+        .take(count)
+        .filter_map(|offset| match width {
+            4 => be_u32(offset).map(u64::from),
+            _ => be_u64(offset),
+        })
+        .collect()
+}
+
+/// Writes the file to `to` with `moov` swapped for `old`.
+fn copy_with(
+    file: &mut File,
+    boxes: &[TopBox],
+    old: &TopBox,
+    moov: &[u8],
+    to: &Path,
+) -> Result<(), Mp4Error> {
+    let mut out = File::create(to)?;
+    for top in boxes {
+        if top.offset == old.offset {
+            out.write_all(moov)?;
+        } else {
+            file.seek(SeekFrom::Start(top.offset))?;
+            io::copy(&mut file.take(top.size), &mut out)?;
+        }
+    }
+    out.sync_all()?;
+    Ok(())
+}
+
+/// `moov` with the tags in place of any earlier ones, other user data kept.
+fn with_tags(moov: &[u8], tags: &Tags) -> Result<Vec<u8>, Mp4Error> {
+    let meta = full_box(b"meta", &[metadata_handler(), tags.ilst()].concat());
+    let mut payload = Vec::new();
+    let mut tagged = false;
+    for (kind, data) in children(moov, "moov")? {
+        if &kind == b"udta" {
+            let mut udta = Vec::new();
+            for (kind, data) in children(data, "udta")? {
+                if &kind != b"meta" {
+                    udta.extend(mp4_box(&kind, data));
+                }
+            }
+            udta.extend(&meta);
+            payload.extend(mp4_box(b"udta", &udta));
+            tagged = true;
+        } else {
+            payload.extend(mp4_box(&kind, data));
+        }
+    }
+    if !tagged {
+        payload.extend(mp4_box(b"udta", &meta));
+    }
+    Ok(mp4_box(b"moov", &payload))
+}
+
+/// Moves every chunk offset at or past `from` by `delta` bytes.
+fn shift_offsets(data: &mut [u8], from: u64, delta: i64) -> Result<(), Mp4Error> {
+    let mut offset = 0;
+    while offset < data.len() {
+        let left = (data.len() - offset) as u64;
+        let (kind, size, header_len) = parse_header(&data[offset..], left)?;
+        let end = offset + size.min(left) as usize;
+        let payload = &mut data[offset + header_len as usize..end];
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_offsets(payload, from, delta)?,
+            b"stco" => shift_table(payload, 4, from, delta)?,
+            b"co64" => shift_table(payload, 8, from, delta)?,
+            _ => {}
+        }
+        offset = end;
+    }
+    Ok(())
+}
+
+fn shift_table(table: &mut [u8], width: usize, from: u64, delta: i64) -> Result<(), Mp4Error> {
+    let count = table.get(4..8).and_then(be_u32).unwrap_or(0) as usize;
+    let entries = match table.get_mut(8..) {
+        Some(entries) => entries,
+        None => return Ok(()),
+    };
+    for entry in entries.chunks_exact_mut(width).take(count) {
+        let offset = match width {
+            4 => be_u32(entry).map(u64::from),
+            _ => be_u64(entry),
+        }
+        .unwrap_or(0);
+        if offset < from {
+            continue;
+        }
+        let moved = (offset as i64 + delta) as u64;
+        match width {
+            4 if moved > u32::MAX as u64 => {
+                return Err(Mp4Error::Invalid(String::from(
+                    "chunk offsets no longer fit in 'stco'",
+                )))
+            }
+            4 => entry.copy_from_slice(&(moved as u32).to_be_bytes()),
+            _ => entry.copy_from_slice(&moved.to_be_bytes()),
+        }
+    }
+    Ok(())
+}
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(payload.len() + 8);
+    data.extend(&(payload.len() as u32 + 8).to_be_bytes());
+    data.extend(kind);
+    data.extend(payload);
+    data
+}
+
+/// A box starting with a version byte and three bytes of flags, all zero.
+fn full_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    mp4_box(kind, &[&[0; 4], payload].concat())
+}
+
+fn metadata_handler() -> Vec<u8> {
+    let mut hdlr = vec![0; 4];
+    hdlr.extend(b"mdirappl");
+    hdlr.extend(&[0; 9]);
+    full_box(b"hdlr", &hdlr)
+}
+
+/// An `ilst` item, its value in a `data` box of the given type.
+fn data_item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = data_type.to_be_bytes().to_vec();
+    data.extend(&[0; 4]);
+    data.extend(value);
+    mp4_box(kind, &mp4_box(b"data", &data))
+}
+
+fn text_item(kind: &[u8; 4], text: &str) -> Vec<u8> {
+    data_item(kind, TEXT_DATA, text.as_bytes())
+}
+
+// Track number out of an unknown total, as an implicitly typed pair.
+fn track_number(episode: u32) -> [u8; 8] {
+    let number = (episode.min(u16::MAX as u32) as u16).to_be_bytes();
+    [0, 0, number[0], number[1], 0, 0, 0, 0]
+}
+
+/// Type, size and header length of the box at the start of `data`, with
+/// `left` bytes from there to the end of its parent.
+fn parse_header(data: &[u8], left: u64) -> Result<([u8; 4], u64, u64), Mp4Error> {
+    let truncated = || Mp4Error::Invalid(String::from("box header cut off"));
+    let kind = match data.get(4..8) {
+        Some(kind) => [kind[0], kind[1], kind[2], kind[3]],
+        None => return Err(truncated()),
+    };
+    let (size, header_len) = match be_u32(&data[0..4]) {
+        // The box runs to the end of its parent.
+        Some(0) => (left, 8),
+        Some(1) => (data.get(8..16).and_then(be_u64).ok_or_else(truncated)?, 16),
+        Some(size) => (size as u64, 8),
+        None => return Err(truncated()),
+    };
+    if size < header_len {
+        return Err(Mp4Error::Invalid(format!(
+            "'{}' box claims to be {} bytes",
+            name(kind),
+            size
+        )));
+    }
+    Ok((kind, size, header_len))
+}
+
+/// Boxes inside `data` with their payloads.
+fn children<'a>(data: &'a [u8], parent: &str) -> Result<Vec<Child<'a>>, Mp4Error> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let left = (data.len() - offset) as u64;
+        let (kind, size, header_len) = parse_header(&data[offset..], left)?;
+        if size > left {
+            return Err(Mp4Error::Invalid(format!(
+                "'{}' box runs past the end of '{}'",
+                name(kind),
+                parent
+            )));
+        }
+        let end = offset + size as usize;
+        boxes.push((kind, &data[offset + header_len as usize..end]));
+        offset = end;
+    }
+    Ok(boxes)
+}
+
+fn find<'a>(boxes: &[Child<'a>], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes
+        .iter()
+        .find(|(k, _)| k == kind)
+        .map(|(_, payload)| *payload)
+}
+
+fn missing(kind: &str, parent: &str) -> Mp4Error {
+    Mp4Error::Invalid(format!("no '{}' box in '{}'", kind, parent))
+}
+
+fn name(kind: [u8; 4]) -> String {
+    String::from_utf8_lossy(&kind).into_owned()
+}
+
+// What a file that isn't an MP4 seems to be, error pages are the usual case.
+fn describe(start: &[u8]) -> String {
+    let text = String::from_utf8_lossy(start);
+    let trimmed = text.trim_start();
+    if start.is_empty() {
+        String::from("the file is empty")
+    } else if trimmed.starts_with('<') {
+        String::from("it looks like an HTML page")
+    } else if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        String::from("it looks like JSON")
+    } else if start
+        .iter()
+        .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+    {
+        format!(
+            "it starts with the text '{}'",
+            trimmed.lines().next().unwrap_or("")
+        )
+    } else {
+        String::from("it doesn't start with an 'ftyp' box")
+    }
+}
+
+fn be_u16(bytes: &[u8]) -> Option<u16> {
+    match bytes {
+        [a, b] => Some(u16::from_be_bytes([*a, *b])),
+        _ => None,
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> Option<u32> {
+    match bytes {
+        [a, b, c, d] => Some(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+fn be_u64(bytes: &[u8]) -> Option<u64> {
+    let mut buf = [0; 8];
+    if bytes.len() != 8 {
+        return None;
+    }
+    buf.copy_from_slice(bytes);
+    Some(u64::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf, process};
+
+    const SAMPLES: [u8; 16] = [0xab; 16];
+
+    /// A one track MP4, `table` is `stco` or `co64`.
+    fn sample(moov_first: bool, table: &[u8; 4]) -> Vec<u8> {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\x02\0isomavc1");
+        let moov = |chunk: u64| {
+            let mut offsets = 1u32.to_be_bytes().to_vec();
+            match table {
+                b"stco" => offsets.extend(&(chunk as u32).to_be_bytes()),
+                _ => offsets.extend(&chunk.to_be_bytes()),
+            }
+            let mut avc1 = vec![0; 24];
+            avc1.extend(&1280u16.to_be_bytes());
+            avc1.extend(&720u16.to_be_bytes());
+            let stsd = [&1u32.to_be_bytes()[..], &mp4_box(b"avc1", &avc1)].concat();
+            let stbl = [full_box(b"stsd", &stsd), full_box(table, &offsets)].concat();
+            let minf = mp4_box(b"stbl", &stbl);
+            let hdlr = full_box(b"hdlr", &[&[0; 4][..], b"vide", &[0; 12]].concat());
+            let mdia = [hdlr, mp4_box(b"minf", &minf)].concat();
+            let trak = mp4_box(b"mdia", &mdia);
+            // 5 seconds at a timescale of 1000.
+            let mvhd = full_box(
+                b"mvhd",
+                &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 232, 0, 0, 19, 136],
+            );
+            mp4_box(b"moov", &[mvhd, mp4_box(b"trak", &trak)].concat())
+        };
+        let mdat = mp4_box(b"mdat", &SAMPLES);
+        let moov_len = moov(0).len() as u64;
+        if moov_first {
+            let chunk = ftyp.len() as u64 + moov_len + 8;
+            [ftyp, moov(chunk), mdat].concat()
+        } else {
+            let chunk = ftyp.len() as u64 + 8;
+            [ftyp, mdat, moov(chunk)].concat()
+        }
+    }
+
+    fn write(name: &str, data: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("twist-{}-{}.mp4", process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// The first chunk offset in `data` and where its samples really are.
+    fn chunk_and_samples(data: &[u8], table: &[u8; 4]) -> (u64, u64) {
+        let at = data.windows(4).position(|w| w == table).unwrap() + 12;
+        let chunk = match table {
+            b"stco" => be_u32(&data[at..at + 4]).unwrap() as u64,
+            _ => be_u64(&data[at..at + 8]).unwrap(),
+        };
+        let samples = data.windows(16).position(|w| w == SAMPLES).unwrap() as u64;
+        (chunk, samples)
+    }
+
+    #[test]
+    fn reads_brand_duration_and_tracks() {
+        let path = write("valid", &sample(true, b"stco"));
+        let info = validate(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(info.brand, "isom");
+        assert_eq!(info.duration, Some(5.0));
+        assert_eq!(
+            info.tracks,
+            vec![Track {
+                handler: String::from("vide"),
+                codec: String::from("avc1"),
+                width: Some(1280),
+                height: Some(720),
+            }]
+        );
+        assert_eq!(info.to_string(), "0:05, avc1 1280x720");
+    }
+
+    #[test]
+    fn tells_what_other_files_are() {
+        let path = write("html", b"\n<!DOCTYPE html><html>Not found</html>");
+        let error = validate(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(&error, Mp4Error::NotMp4(what) if what.contains("HTML")));
+
+        let path = write("empty", b"");
+        let error = validate(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(&error, Mp4Error::NotMp4(what) if what.contains("empty")));
+    }
+
+    #[test]
+    fn rejects_cut_off_files() {
+        let data = sample(true, b"stco");
+        let path = write("cut", &data[..data.len() - 4]);
+        let error = validate(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(&error, Mp4Error::Invalid(message) if message.contains("'mdat'")));
+    }
+
+    #[test]
+    fn rejects_samples_outside_mdat() {
+        let mut data = sample(false, b"stco");
+        let at = data.windows(4).position(|w| w == b"stco").unwrap() + 12;
+        data[at..at + 4].copy_from_slice(&2u32.to_be_bytes());
+        let path = write("outside", &data);
+        let error = validate(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(&error, Mp4Error::Invalid(message) if message.contains("byte 2")));
+    }
+
+    fn tags() -> Tags {
+        Tags {
+            title: String::from("Episode 3"),
+            show: String::from("Mushishi"),
+            episode: Some(3),
+            season: None,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn tagging_shifts_offsets_behind_moov() {
+        for table in &[b"stco", b"co64"] {
+            let path = write("moov-first", &sample(true, table));
+            write_tags(&path, &tags()).unwrap();
+            let data = fs::read(&path).unwrap();
+            let info = validate(&path);
+            fs::remove_file(&path).unwrap();
+
+            assert!(info.is_ok());
+            let (chunk, samples) = chunk_and_samples(&data, table);
+            assert_eq!(chunk, samples);
+            assert!(data.windows(8).any(|w| w == b"Mushishi"));
+            assert!(data.windows(4).any(|w| w == b"tves"));
+        }
+    }
+
+    #[test]
+    fn tagging_keeps_offsets_in_front_of_moov() {
+        let original = sample(false, b"stco");
+        let path = write("moov-last", &original);
+        write_tags(&path, &tags()).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            chunk_and_samples(&data, b"stco"),
+            chunk_and_samples(&original, b"stco")
+        );
+        assert!(data.len() > original.len());
+    }
+
+    #[test]
+    fn tagging_again_replaces_the_tags() {
+        let path = write("retag", &sample(true, b"stco"));
+        write_tags(&path, &tags()).unwrap();
+        let once = fs::read(&path).unwrap();
+        write_tags(&path, &tags()).unwrap();
+        let twice = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(once, twice);
+    }
+}
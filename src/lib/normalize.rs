@@ -0,0 +1,132 @@
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// A title folded so that spelling variants compare equal: case, diacritics,
+/// full-width forms, punctuation and the common romanizations of long vowels
+/// and the particle を ("ou", "oo" and "ō" all become "o", "wo" becomes "o").
+#[derive(Debug, Clone)]
+pub struct Normalized {
+    pub text: String,
+    // Char position in the original string for every char of `text`.
+    origin: Vec<usize>,
+}
+
+impl Normalized {
+    pub fn new(original: &str) -> Self {
+        let chars = fold_chars(original);
+        let chars = fold_punctuation(chars);
+        let chars = fold_romanization(chars);
+
+        let (text, origin) = chars.into_iter().unzip();
+        Self { text, origin }
+    }
+
+    /// Maps char positions in `text` back to the original string.
+    pub fn original_indices(&self, indices: &[usize]) -> Vec<usize> {
+        let mut original: Vec<usize> = indices
+            .iter()
+            .filter_map(|i| self.origin.get(*i).copied())
+            .collect();
+        original.dedup();
+        original
+    }
+}
+
+pub fn normalize_query(query: &str) -> String {
+    Normalized::new(query).text
+}
+
+// NFKD turns full-width letters into ASCII and splits off diacritics, which
+// are then dropped.
+fn fold_chars(original: &str) -> Vec<(char, usize)> {
+    let mut chars = Vec::with_capacity(original.len());
+    for (i, c) in original.chars().enumerate() {
+        for decomposed in std::iter::once(c).nfkd() {
+            if is_combining_mark(decomposed) {
+                continue;
+            }
+            chars.extend(decomposed.to_lowercase().map(|lower| (lower, i)));
+        }
+    }
+    chars
+}
+
+// Punctuation separates words like whitespace does ("Re:Zero" is "re zero"),
+// runs of separators become a single space.
+fn fold_punctuation(chars: Vec<(char, usize)>) -> Vec<(char, usize)> {
+    let mut folded: Vec<(char, usize)> = Vec::with_capacity(chars.len());
+    for (c, i) in chars {
+        if c.is_alphanumeric() {
+            folded.push((c, i));
+        } else if !folded.is_empty() && folded.last().map(|(c, _)| *c) != Some(' ') {
+            folded.push((' ', i));
+        }
+    }
+    if folded.last().map(|(c, _)| *c) == Some(' ') {
+        folded.pop();
+    }
+    folded
+}
+
+fn fold_romanization(chars: Vec<(char, usize)>) -> Vec<(char, usize)> {
+    let mut folded: Vec<(char, usize)> = Vec::with_capacity(chars.len());
+    for (n, &(c, i)) in chars.iter().enumerate() {
+        let previous = folded.last().map(|(c, _)| *c);
+        match (previous, c) {
+            // Long vowels.
+            (Some('o'), 'u') | (Some('o'), 'o') | (Some('u'), 'u') => continue,
+            _ => {}
+        }
+        // The particle "wo" as a word of its own.
+        let word_start = matches!(previous, None | Some(' '));
+        let next = chars.get(n + 1).map(|(c, _)| *c);
+        let after = chars.get(n + 2).map(|(c, _)| *c);
+        if c == 'w' && word_start && next == Some('o') && matches!(after, None | Some(' ')) {
+            continue;
+        }
+        folded.push((c, i));
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_case_diacritics_and_width() {
+        assert_eq!(normalize_query("Pokémon"), "pokemon");
+        assert_eq!(normalize_query("ＡＢＣ"), "abc");
+    }
+
+    #[test]
+    fn punctuation_separates_words() {
+        assert_eq!(normalize_query("Re:Zero"), "re zero");
+        assert_eq!(normalize_query("  Fate/stay -- night! "), "fate stay night");
+    }
+
+    #[test]
+    fn long_vowels_compare_equal() {
+        assert_eq!(normalize_query("Shōjo"), "shojo");
+        assert_eq!(normalize_query("Shoujo"), "shojo");
+        assert_eq!(normalize_query("Shoojo"), "shojo");
+        assert_eq!(normalize_query("Yuusha"), "yusha");
+    }
+
+    #[test]
+    fn folds_the_particle_wo_only_as_a_word() {
+        assert_eq!(normalize_query("Kimi wo Wasurenai"), "kimi o wasurenai");
+        assert_eq!(normalize_query("Wolf"), "wolf");
+        assert_eq!(normalize_query("Two"), "two");
+    }
+
+    #[test]
+    fn maps_positions_back_to_the_original() {
+        let normalized = Normalized::new("Re:Zōō");
+        assert_eq!(normalized.text, "re zo");
+        assert_eq!(
+            normalized.original_indices(&[0, 1, 2, 3, 4]),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(normalized.original_indices(&[4, 9]), vec![4]);
+    }
+}
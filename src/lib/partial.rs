@@ -0,0 +1,248 @@
+use crate::{
+    format::VideoFormat,
+    hls::HlsProgress,
+    mp4::{validate, Mp4Error},
+};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{de, ser};
+use std::{
+    error::Error,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+pub static PART_EXTENSION: &str = "part";
+pub static SIDECAR_EXTENSION: &str = "json";
+
+/// What a `.part` file should turn into, kept in a sidecar next to it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PartInfo {
+    /// See `downloads::hash_source`, a different source starts over.
+    pub source_hash: String,
+    pub expected_size: Option<u64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Set once the source turned out to be an HLS playlist.
+    pub hls: Option<HlsProgress>,
+}
+
+/// A download in progress. `3.mp4` is written as `3.mp4.part` with the
+/// sidecar `3.mp4.part.json`, and only renamed once it is complete.
+#[derive(Debug)]
+pub struct PartFile {
+    pub file: File,
+    pub path: PathBuf,
+    pub info: PartInfo,
+    target: PathBuf,
+}
+
+impl PartFile {
+    /// Picks up where an earlier attempt at `target` stopped. An existing
+    /// `target` is left alone until the download replaces it.
+    ///
+    /// The part file stays locked while it is open. Fails with an error
+    /// `is_busy` recognizes when another twist is writing to it.
+    pub fn open(target: &Path, source_hash: &str) -> io::Result<PartFile> {
+        let path = part_path(target);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(e);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} is being downloaded by another twist", path.display()),
+            ));
+        }
+        if file.metadata()?.len() == 0 && is_cut_off(target) {
+            fs::remove_file(target)?;
+        }
+
+        let mut info = fs::read_to_string(sidecar_path(&path))
+            .ok()
+            .and_then(|s| de::from_str::<PartInfo>(&s).ok())
+            .unwrap_or_default();
+        // Without a source hash the bytes can't be told to belong to this
+        // source, so they are dropped as well.
+        if info.source_hash != source_hash {
+            file.set_len(0)?;
+            info = PartInfo::default();
+        }
+        info.source_hash = source_hash.to_string();
+
+        Ok(PartFile {
+            file,
+            path,
+            info,
+            target: target.to_path_buf(),
+        })
+    }
+
+    pub fn save_info(&self) -> Result<(), Box<dyn Error>> {
+        fs::write(sidecar_path(&self.path), ser::to_string_pretty(&self.info)?)?;
+        Ok(())
+    }
+
+    /// Fails when the file is shorter or longer than the server said.
+    pub fn check_size(&self) -> Result<(), Box<dyn Error>> {
+        let size = self.file.metadata()?.len();
+        match self.info.expected_size {
+            Some(expected) if size != expected => {
+                Err(format!("Download incomplete, got {} of {} bytes", size, expected).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Flushes the data to disk and renames the file to its final name.
+    pub fn finish(self) -> Result<PathBuf, Box<dyn Error>> {
+        self.check_size()?;
+        self.file.sync_all()?;
+        fs::rename(&self.path, &self.target)?;
+        let _ = fs::remove_file(sidecar_path(&self.path));
+        sync_dir(&self.target);
+        Ok(self.target)
+    }
+
+    /// Deletes the part file and its sidecar, the next attempt starts over.
+    pub fn discard(self) -> io::Result<()> {
+        // Open files can't be removed on Windows.
+        drop(self.file);
+        let _ = fs::remove_file(sidecar_path(&self.path));
+        fs::remove_file(&self.path)
+    }
+
+    /// Where the file goes once it is complete.
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+}
+
+// Downloads from before part files were written to `target` directly. One
+// that was cut off can't be resumed without knowing its source.
+fn is_cut_off(target: &Path) -> bool {
+    VideoFormat::from_path(target) == Some(VideoFormat::Mp4)
+        && matches!(
+            validate(target),
+            Err(Mp4Error::Invalid(_)) | Err(Mp4Error::NotMp4(_))
+        )
+}
+
+/// Whether `error` is `PartFile::open` finding the part file locked.
+pub fn is_busy(error: &(dyn Error + 'static)) -> bool {
+    matches!(error.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+/// Makes a rename to `path` itself durable, where directories can be synced.
+#[cfg(unix)]
+pub fn sync_dir(path: &Path) {
+    if let Some(dir) = path.parent() {
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn sync_dir(_path: &Path) {}
+
+pub fn part_path(target: &Path) -> PathBuf {
+    with_suffix(target, PART_EXTENSION)
+}
+
+pub fn sidecar_path(part: &Path) -> PathBuf {
+    with_suffix(part, SIDECAR_EXTENSION)
+}
+
+fn with_suffix(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+pub fn is_part(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext == PART_EXTENSION)
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext == SIDECAR_EXTENSION)
+        && is_part(&path.with_extension(""))
+}
+
+/// `3.mp4` for `3.mp4.part`.
+pub fn target_path(part: &Path) -> Option<PathBuf> {
+    if is_part(part) {
+        Some(part.with_extension(""))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn target(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("twist-part-{}-{}.mp4", process::id(), name))
+    }
+
+    #[test]
+    fn a_part_file_is_only_opened_once() {
+        let target = target("locked");
+        let part = PartFile::open(&target, "hash").unwrap();
+        let error = PartFile::open(&target, "hash").unwrap_err();
+        assert!(is_busy(&error));
+        part.discard().unwrap();
+        PartFile::open(&target, "hash").unwrap().discard().unwrap();
+    }
+
+    #[test]
+    fn finished_episodes_stay_until_they_are_replaced() {
+        let target = target("finished").with_extension("mkv");
+        fs::write(&target, b"a finished episode").unwrap();
+        let part = PartFile::open(&target, "hash").unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"a finished episode");
+        assert_eq!(part.file.metadata().unwrap().len(), 0);
+        part.discard().unwrap();
+        fs::remove_file(&target).unwrap();
+    }
+
+    #[test]
+    fn cut_off_downloads_from_before_part_files_start_over() {
+        let target = target("cut-off");
+        fs::write(&target, b"\0\0\0\x20ftypisom").unwrap();
+        let part = PartFile::open(&target, "hash").unwrap();
+        assert!(!target.exists());
+        assert_eq!(part.file.metadata().unwrap().len(), 0);
+        part.discard().unwrap();
+    }
+
+    #[test]
+    fn parts_of_an_unknown_source_start_over() {
+        let target = target("unknown");
+        fs::write(part_path(&target), b"some bytes").unwrap();
+        let part = PartFile::open(&target, "hash").unwrap();
+        assert_eq!(part.file.metadata().unwrap().len(), 0);
+        part.save_info().unwrap();
+        drop(part);
+
+        fs::write(part_path(&target), b"some bytes").unwrap();
+        let part = PartFile::open(&target, "hash").unwrap();
+        assert_eq!(part.file.metadata().unwrap().len(), 10);
+        assert_eq!(part.info.source_hash, "hash");
+        let part = {
+            drop(part);
+            PartFile::open(&target, "other").unwrap()
+        };
+        assert_eq!(part.file.metadata().unwrap().len(), 0);
+        part.discard().unwrap();
+    }
+}
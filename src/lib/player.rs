@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Command, Stdio},
+};
+use tokio::task;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlayerOptions {
+    /// Player executable, it has to understand mpv's options.
+    pub command: String,
+    /// Share of an episode after which it counts as watched.
+    pub watched_threshold: f64,
+}
+
+impl Default for PlayerOptions {
+    fn default() -> Self {
+        Self {
+            command: String::from("mpv"),
+            watched_threshold: 0.9,
+        }
+    }
+}
+
+/// What mpv reported about an episode until it was closed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Playback {
+    /// Seconds into the episode.
+    pub position: Option<f64>,
+    pub duration: Option<f64>,
+    /// Playback ran to the end instead of being quit.
+    pub reached_end: bool,
+}
+
+impl Playback {
+    pub fn is_watched(&self, threshold: f64) -> bool {
+        if self.reached_end {
+            return true;
+        }
+        match (self.position, self.duration) {
+            (Some(position), Some(duration)) if duration > 0.0 => position / duration >= threshold,
+            _ => false,
+        }
+    }
+}
+
+/// Something mpv can open, with the headers needed to stream it.
+#[derive(Debug, Clone)]
+pub struct Media {
+    pub source: String,
+    pub title: String,
+    pub headers: Vec<(String, String)>,
+    pub user_agent: Option<String>,
+}
+
+/// Plays `media` in mpv from `start` seconds on and follows the playback over
+/// mpv's JSON IPC until the player is closed.
+pub async fn play(
+    media: Media,
+    start: Option<f64>,
+    options: &PlayerOptions,
+) -> Result<Playback, Box<dyn Error>> {
+    let mut command = Command::new(&options.command);
+    command
+        .arg("--no-terminal")
+        .arg(format!("--force-media-title={}", media.title))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Some(start) = start {
+        command.arg(format!("--start={:.0}", start));
+    }
+    if !media.headers.is_empty() {
+        let fields: Vec<String> = media
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        command.arg(format!("--http-header-fields={}", fields.join(",")));
+    }
+    if let Some(user_agent) = &media.user_agent {
+        command.arg(format!("--user-agent={}", user_agent));
+    }
+
+    let playback = ipc::run(command, media.source).await?;
+    Ok(playback)
+}
+
+/// Sends the property observers to mpv and reads its events from `stream`
+/// until the connection is closed.
+pub fn follow_playback<S: Read + Write>(mut stream: S) -> io::Result<Playback> {
+    stream.write_all(b"{\"command\": [\"observe_property\", 1, \"time-pos\"]}\n")?;
+    stream.write_all(b"{\"command\": [\"observe_property\", 2, \"duration\"]}\n")?;
+    stream.flush()?;
+
+    let mut playback = Playback::default();
+    for line in BufReader::new(stream).lines() {
+        let event: Value = match serde_json::from_str(&line?) {
+            Ok(event) => event,
+            Err(_) => continue,
+        };
+        match event["event"].as_str() {
+            // The position goes back to null while the file is unloaded.
+            Some("property-change") => match (event["name"].as_str(), event["data"].as_f64()) {
+                (Some("time-pos"), Some(position)) => playback.position = Some(position),
+                (Some("duration"), Some(duration)) => playback.duration = Some(duration),
+                _ => {}
+            },
+            Some("end-file") => playback.reached_end = event["reason"] == "eof",
+            _ => {}
+        }
+    }
+    Ok(playback)
+}
+
+/// Formats seconds as `m:ss` or `h:mm:ss`.
+pub fn timestamp(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(unix)]
+pub mod ipc {
+    use super::{follow_playback, task, Playback};
+    use std::{
+        env, fs, io,
+        os::unix::net::UnixStream,
+        path::{Path, PathBuf},
+        process::{self, Command},
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// How long mpv gets to open its socket.
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Plays started by this process, every one gets its own socket.
+    static PLAYS: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn socket_path() -> PathBuf {
+        let play = PLAYS.fetch_add(1, Ordering::Relaxed);
+        env::temp_dir().join(format!("twist-mpv-{}-{}.sock", process::id(), play))
+    }
+
+    pub async fn run(mut command: Command, source: String) -> io::Result<Playback> {
+        let socket = socket_path();
+        let _ = fs::remove_file(&socket);
+        let mut child = command
+            .arg(format!("--input-ipc-server={}", socket.display()))
+            .arg(source)
+            .spawn()?;
+
+        task::spawn_blocking(move || {
+            let playback = connect(&socket, CONNECT_TIMEOUT, || {
+                !matches!(child.try_wait(), Ok(None))
+            })
+            .and_then(follow_playback);
+            let _ = child.wait();
+            let _ = fs::remove_file(&socket);
+            playback
+        })
+        .await?
+    }
+
+    /// Connects to the socket once it shows up, or gives up when `exited`
+    /// says the player is gone.
+    pub fn connect<F>(path: &Path, timeout: Duration, mut exited: F) -> io::Result<UnixStream>
+    where
+        F: FnMut() -> bool,
+    {
+        let start = Instant::now();
+        loop {
+            match UnixStream::connect(path) {
+                Ok(stream) => return Ok(stream),
+                Err(e) if exited() || start.elapsed() > timeout => return Err(e),
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+}
+
+// Without unix sockets the episode plays, but nothing is tracked.
+#[cfg(not(unix))]
+pub mod ipc {
+    use super::{task, Playback};
+    use std::{io, process::Command};
+
+    pub async fn run(mut command: Command, source: String) -> io::Result<Playback> {
+        let mut child = command.arg(source).spawn()?;
+        task::spawn_blocking(move || child.wait().map(|_| Playback::default())).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Replays what mpv would send and keeps what was sent to it.
+    struct FakeMpv {
+        events: Cursor<&'static [u8]>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for FakeMpv {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.events.read(buf)
+        }
+    }
+
+    impl Write for FakeMpv {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn observes_position_and_duration() {
+        let mut mpv = FakeMpv {
+            events: Cursor::new(
+                b"{\"event\": \"property-change\", \"id\": 2, \"name\": \"duration\", \"data\": 1420.5}\n\
+                  {\"event\": \"property-change\", \"id\": 1, \"name\": \"time-pos\", \"data\": 300.25}\n\
+                  not json\n\
+                  {\"event\": \"property-change\", \"id\": 1, \"name\": \"time-pos\", \"data\": null}\n\
+                  {\"event\": \"end-file\", \"reason\": \"quit\"}\n",
+            ),
+            sent: Vec::new(),
+        };
+        let playback = follow_playback(&mut mpv).unwrap();
+        assert_eq!(
+            playback,
+            Playback {
+                position: Some(300.25),
+                duration: Some(1420.5),
+                reached_end: false,
+            }
+        );
+        assert!(!playback.is_watched(0.9));
+        let sent = String::from_utf8(mpv.sent).unwrap();
+        assert!(sent.contains("[\"observe_property\", 1, \"time-pos\"]"));
+        assert!(sent.contains("[\"observe_property\", 2, \"duration\"]"));
+    }
+
+    #[test]
+    fn watched_once_the_threshold_is_passed() {
+        let playback = Playback {
+            position: Some(1300.0),
+            duration: Some(1420.0),
+            reached_end: false,
+        };
+        assert!(playback.is_watched(0.9));
+        assert!(!playback.is_watched(0.95));
+        assert!(!Playback::default().is_watched(0.0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn end_of_file_over_the_socket_marks_the_episode_watched() {
+        use std::{os::unix::net::UnixListener, thread, time::Duration};
+
+        let path = ipc::socket_path();
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut commands = BufReader::new(stream.try_clone().unwrap()).lines();
+            let observed = [commands.next(), commands.next()];
+            stream
+                .write_all(
+                    b"{\"event\": \"property-change\", \"name\": \"time-pos\", \"data\": 12.0}\n\
+                      {\"event\": \"end-file\", \"reason\": \"eof\"}\n",
+                )
+                .unwrap();
+            observed.iter().all(|line| matches!(line, Some(Ok(_))))
+        });
+
+        let stream = ipc::connect(&path, Duration::from_secs(5), || false).unwrap();
+        let playback = follow_playback(stream).unwrap();
+        assert!(server.join().unwrap());
+        assert_eq!(playback.position, Some(12.0));
+        assert!(playback.reached_end);
+        assert!(playback.is_watched(0.9));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn every_play_gets_its_own_socket() {
+        assert_ne!(ipc::socket_path(), ipc::socket_path());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{metadata::escape, player::Media};
+use serde_json::{ser, Map, Value};
+use std::path::Path;
+use url::Url;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    #[default]
+    M3u8,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+
+    pub fn render(self, title: &str, entries: &[Entry]) -> String {
+        match self {
+            PlaylistFormat::M3u8 => m3u8(title, entries),
+            PlaylistFormat::Xspf => xspf(title, entries),
+        }
+    }
+}
+
+/// One episode of a playlist, entries are written in the given order.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub number: i64,
+    pub media: Media,
+    /// Seconds.
+    pub duration: Option<f64>,
+}
+
+// Headers go in two ways, VLC's options and the `#EXTHTTP` JSON object
+// other players read. Players that know neither play what doesn't need them.
+fn m3u8(title: &str, entries: &[Entry]) -> String {
+    let mut m3u = format!("#EXTM3U\n#PLAYLIST:{}\n", one_line(title));
+    for entry in entries {
+        let media = &entry.media;
+        let duration = entry
+            .duration
+            .map_or(-1, |duration| duration.round() as i64);
+        m3u.push_str(&format!(
+            "#EXTINF:{},{}\n",
+            duration,
+            one_line(&media.title)
+        ));
+        for option in vlc_options(media) {
+            m3u.push_str(&format!("#EXTVLCOPT:{}\n", option));
+        }
+        let mut headers: Map<String, Value> = media
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::from(value.as_str())))
+            .collect();
+        if let Some(user_agent) = &media.user_agent {
+            headers.insert(String::from("User-Agent"), Value::from(user_agent.as_str()));
+        }
+        if !headers.is_empty() {
+            let json = ser::to_string(&headers).unwrap_or_default();
+            m3u.push_str(&format!("#EXTHTTP:{}\n", json));
+        }
+        m3u.push_str(&format!("{}\n", media.source));
+    }
+    m3u
+}
+
+fn xspf(title: &str, entries: &[Entry]) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\" ",
+        "xmlns:vlc=\"http://www.videolan.org/vlc/playlist/ns/0/\">\n"
+    ));
+    xml.push_str(&format!(
+        "  <title>{}</title>\n  <trackList>\n",
+        escape(title)
+    ));
+    for entry in entries {
+        let media = &entry.media;
+        xml.push_str("    <track>\n");
+        xml.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape(&location(&media.source))
+        ));
+        xml.push_str(&format!("      <title>{}</title>\n", escape(&media.title)));
+        xml.push_str(&format!("      <trackNum>{}</trackNum>\n", entry.number));
+        if let Some(duration) = entry.duration {
+            let millis = (duration * 1000.0).round() as u64;
+            xml.push_str(&format!("      <duration>{}</duration>\n", millis));
+        }
+        let options = vlc_options(media);
+        if !options.is_empty() {
+            xml.push_str(
+                "      <extension application=\"http://www.videolan.org/vlc/playlist/0\">\n",
+            );
+            for option in options {
+                xml.push_str(&format!(
+                    "        <vlc:option>{}</vlc:option>\n",
+                    escape(&option)
+                ));
+            }
+            xml.push_str("      </extension>\n");
+        }
+        xml.push_str("    </track>\n");
+    }
+    xml.push_str("  </trackList>\n</playlist>\n");
+    xml
+}
+
+/// VLC can only set the referrer and the user agent.
+fn vlc_options(media: &Media) -> Vec<String> {
+    let mut options = Vec::new();
+    let referrer = media
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("referer"));
+    if let Some((_, referrer)) = referrer {
+        options.push(format!("http-referrer={}", one_line(referrer)));
+    }
+    if let Some(user_agent) = &media.user_agent {
+        options.push(format!("http-user-agent={}", one_line(user_agent)));
+    }
+    options
+}
+
+// XSPF wants an URI, local files become `file://` ones.
+fn location(source: &str) -> String {
+    match Url::parse(source) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url.to_string(),
+        _ => Url::from_file_path(Path::new(source))
+            .map(|url| url.to_string())
+            .unwrap_or_else(|_| source.to_string()),
+    }
+}
+
+// A line break would end the M3U line early.
+fn one_line(text: &str) -> String {
+    text.replace(&['\r', '\n'][..], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                number: 1,
+                media: Media {
+                    source: String::from("/anime/Mushishi/1.mp4"),
+                    title: String::from("Mushishi - 1"),
+                    headers: Vec::new(),
+                    user_agent: None,
+                },
+                duration: Some(1420.4),
+            },
+            Entry {
+                number: 2,
+                media: Media {
+                    source: String::from("https://cdn.example.com/2.mp4?a=1&b=2"),
+                    title: String::from("Mushishi\n- 2"),
+                    headers: vec![(String::from("Referer"), String::from("https://twist.moe/"))],
+                    user_agent: Some(String::from("twist")),
+                },
+                duration: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn renders_m3u8() {
+        assert_eq!(
+            PlaylistFormat::M3u8.render("Mushishi", &entries()),
+            "#EXTM3U\n\
+             #PLAYLIST:Mushishi\n\
+             #EXTINF:1420,Mushishi - 1\n\
+             /anime/Mushishi/1.mp4\n\
+             #EXTINF:-1,Mushishi - 2\n\
+             #EXTVLCOPT:http-referrer=https://twist.moe/\n\
+             #EXTVLCOPT:http-user-agent=twist\n\
+             #EXTHTTP:{\"Referer\":\"https://twist.moe/\",\"User-Agent\":\"twist\"}\n\
+             https://cdn.example.com/2.mp4?a=1&b=2\n"
+        );
+    }
+
+    #[test]
+    fn renders_xspf() {
+        let xml = PlaylistFormat::Xspf.render("Tom & Jerry", &entries());
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(xml.contains("  <title>Tom &amp; Jerry</title>\n"));
+        assert!(xml.contains(
+            "    <track>\n\
+             \x20     <location>file:///anime/Mushishi/1.mp4</location>\n\
+             \x20     <title>Mushishi - 1</title>\n\
+             \x20     <trackNum>1</trackNum>\n\
+             \x20     <duration>1420400</duration>\n\
+             \x20   </track>\n"
+        ));
+        assert!(
+            xml.contains("      <location>https://cdn.example.com/2.mp4?a=1&amp;b=2</location>\n")
+        );
+        assert!(xml.contains("        <vlc:option>http-referrer=https://twist.moe/</vlc:option>\n"));
+        assert!(xml.ends_with("    </track>\n  </trackList>\n</playlist>\n"));
+    }
+}
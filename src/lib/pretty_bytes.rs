@@ -7,7 +7,8 @@ pub fn convert(num: u64) -> String {
     let exponent = cmp::min(
         ((num as f64).ln() / delimiter.ln()).floor() as i32,
         (units.len() - 1) as i32,
-    );
+    )
+    .max(0); // ln(0) is -inf.
     let pretty_bytes = (num as f64 / delimiter.powi(exponent)) * 1_f64;
     let unit = units[exponent as usize];
     format!("{:.2} {}", pretty_bytes, unit)
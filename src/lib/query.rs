@@ -0,0 +1,278 @@
+use crate::{
+    normalize::normalize_query,
+    types::{Anime, ID},
+};
+use std::{collections::HashSet, error::Error, fmt};
+
+/// A parsed search box query, e.g. `ongoing:yes eps:>24 shingeki`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    /// Everything that is not a filter, matched fuzzily.
+    pub text: String,
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Ongoing(bool),
+    Downloaded(bool),
+    Followed(bool),
+    Id(ID),
+    Episodes(Comparison, usize),
+    Alt(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// What filters need to know that isn't part of `Anime` itself.
+#[derive(Debug, Clone, Default)]
+pub struct FilterContext {
+    pub downloaded: HashSet<ID>,
+    pub followed: HashSet<ID>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte offset in the query where the offending term starts.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at {})", self.message, self.position)
+    }
+}
+
+impl Error for ParseError {}
+
+static KEYS: &[&str] = &["ongoing", "downloaded", "followed", "id", "eps", "alt"];
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, ParseError> {
+        let mut text = Vec::new();
+        let mut filters = Vec::new();
+
+        for (position, term) in split_terms(input)? {
+            let filter = match term.find(':') {
+                Some(colon) if KEYS.contains(&term[..colon].to_lowercase().as_str()) => {
+                    let (key, value) = (&term[..colon], unquote(&term[colon + 1..]));
+                    parse_filter(&key.to_lowercase(), value)
+                        .map_err(|message| ParseError { position, message })?
+                }
+                // Titles like "Re:Zero" contain colons too.
+                _ => {
+                    text.push(unquote(term));
+                    continue;
+                }
+            };
+            filters.push(filter);
+        }
+
+        Ok(Query {
+            text: text.join(" "),
+            filters,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.filters.is_empty()
+    }
+
+    pub fn matches_filters(&self, anime: &Anime, context: &FilterContext) -> bool {
+        self.filters
+            .iter()
+            .all(|filter| filter.matches(anime, context))
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, anime: &Anime, context: &FilterContext) -> bool {
+        match self {
+            Filter::Ongoing(yes) => anime.ongoing == *yes,
+            Filter::Downloaded(yes) => context.downloaded.contains(&anime.id) == *yes,
+            Filter::Followed(yes) => context.followed.contains(&anime.id) == *yes,
+            Filter::Id(id) => anime.id == *id,
+            Filter::Episodes(comparison, count) => match anime.episode_count {
+                Some(episodes) => comparison.compare(episodes, *count),
+                None => false,
+            },
+            Filter::Alt(alt) => match &anime.alt_title {
+                Some(alt_title) => normalize_query(alt_title).contains(&normalize_query(alt)),
+                None => false,
+            },
+        }
+    }
+}
+
+impl Comparison {
+    fn compare(self, left: usize, right: usize) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Equal => left == right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Greater => left > right,
+        }
+    }
+}
+
+fn parse_filter(key: &str, value: &str) -> Result<Filter, String> {
+    match key {
+        "ongoing" => parse_bool(key, value).map(Filter::Ongoing),
+        "downloaded" => parse_bool(key, value).map(Filter::Downloaded),
+        "followed" => parse_bool(key, value).map(Filter::Followed),
+        "id" => value
+            .parse()
+            .map(Filter::Id)
+            .map_err(|_| format!("id: expected a number, got '{}'", value)),
+        "eps" => {
+            let (comparison, number) = if let Some(rest) = value.strip_prefix(">=") {
+                (Comparison::GreaterOrEqual, rest)
+            } else if let Some(rest) = value.strip_prefix("<=") {
+                (Comparison::LessOrEqual, rest)
+            } else if let Some(rest) = value.strip_prefix('>') {
+                (Comparison::Greater, rest)
+            } else if let Some(rest) = value.strip_prefix('<') {
+                (Comparison::Less, rest)
+            } else {
+                (Comparison::Equal, value.trim_start_matches('='))
+            };
+            number
+                .parse()
+                .map(|count| Filter::Episodes(comparison, count))
+                .map_err(|_| format!("eps: expected e.g. 12, >24 or <=13, got '{}'", value))
+        }
+        "alt" if value.is_empty() => Err(String::from("alt: expected a title")),
+        "alt" => Ok(Filter::Alt(value.to_string())),
+        _ => Err(format!("unknown filter '{}'", key)),
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" | "y" | "true" | "1" => Ok(true),
+        "no" | "n" | "false" | "0" => Ok(false),
+        _ => Err(format!("{}: expected yes or no, got '{}'", key, value)),
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    s.trim_matches('"')
+}
+
+/// Splits on whitespace, except inside double quotes. Yields each term with
+/// its byte offset.
+fn split_terms(input: &str) -> Result<Vec<(usize, &str)>, ParseError> {
+    let mut terms = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' if quote.is_some() => quote = None,
+            '"' => {
+                quote = Some(i);
+                start.get_or_insert(i);
+            }
+            c if c.is_whitespace() && quote.is_none() => {
+                if let Some(s) = start.take() {
+                    terms.push((s, &input[s..i]));
+                }
+            }
+            _ => {
+                start.get_or_insert(i);
+            }
+        }
+    }
+
+    if let Some(position) = quote {
+        return Err(ParseError {
+            position,
+            message: String::from("unterminated quote"),
+        });
+    }
+    if let Some(s) = start {
+        terms.push((s, &input[s..]));
+    }
+    Ok(terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_filters_from_text() {
+        let query = Query::parse("ongoing:yes eps:>12 shingeki no").unwrap();
+        assert_eq!(query.text, "shingeki no");
+        assert_eq!(
+            query.filters,
+            vec![
+                Filter::Ongoing(true),
+                Filter::Episodes(Comparison::Greater, 12)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_every_comparison() {
+        let parse = |value: &str| Query::parse(&format!("eps:{}", value)).unwrap().filters;
+        assert_eq!(parse("12"), vec![Filter::Episodes(Comparison::Equal, 12)]);
+        assert_eq!(parse("=12"), vec![Filter::Episodes(Comparison::Equal, 12)]);
+        assert_eq!(parse("<12"), vec![Filter::Episodes(Comparison::Less, 12)]);
+        assert_eq!(
+            parse("<=12"),
+            vec![Filter::Episodes(Comparison::LessOrEqual, 12)]
+        );
+        assert_eq!(
+            parse(">=12"),
+            vec![Filter::Episodes(Comparison::GreaterOrEqual, 12)]
+        );
+    }
+
+    #[test]
+    fn keeps_colons_of_unknown_keys_in_the_text() {
+        let query = Query::parse("Re:Zero").unwrap();
+        assert_eq!(query.text, "Re:Zero");
+        assert!(query.filters.is_empty());
+    }
+
+    #[test]
+    fn keys_are_case_insensitive() {
+        let query = Query::parse("Followed:No ID:42").unwrap();
+        assert_eq!(query.filters, vec![Filter::Followed(false), Filter::Id(42)]);
+    }
+
+    #[test]
+    fn quotes_keep_spaces_together() {
+        let query = Query::parse("alt:\"attack on\" titan").unwrap();
+        assert_eq!(query.text, "titan");
+        assert_eq!(query.filters, vec![Filter::Alt(String::from("attack on"))]);
+    }
+
+    #[test]
+    fn reports_where_a_term_went_wrong() {
+        let error = Query::parse("naruto eps:lots").unwrap_err();
+        assert_eq!(error.position, 7);
+        let error = Query::parse("naruto ongoing:maybe").unwrap_err();
+        assert_eq!(error.position, 7);
+        let error = Query::parse("alt: naruto").unwrap_err();
+        assert_eq!(error.position, 0);
+        let error = Query::parse("say \"hi").unwrap_err();
+        assert_eq!(error.position, 4);
+    }
+
+    #[test]
+    fn empty_queries() {
+        assert!(Query::parse("").unwrap().is_empty());
+        assert!(Query::parse("   ").unwrap().is_empty());
+    }
+}
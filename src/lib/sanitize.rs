@@ -0,0 +1,167 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Most filesystems (ext4, NTFS, APFS) cap a single path component at 255 bytes.
+pub const MAX_FILENAME_BYTES: usize = 255;
+
+static RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+static RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const REPLACEMENT: char = '_';
+
+/// Turns an arbitrary title into a single path component that is valid on
+/// Windows, macOS and Linux. This is only meant for names on disk, the api
+/// slug is derived separately.
+pub fn sanitize_filename(name: &str) -> String {
+    sanitize_with_limit(name, MAX_FILENAME_BYTES)
+}
+
+pub fn sanitize_with_limit(name: &str, max_bytes: usize) -> String {
+    let normalized: String = name.nfc().collect();
+
+    let mut cleaned = String::with_capacity(normalized.len());
+    for c in normalized.chars() {
+        if c.is_control() || RESERVED_CHARS.contains(&c) {
+            cleaned.push(REPLACEMENT);
+        } else if c.is_whitespace() {
+            cleaned.push(' ');
+        } else {
+            cleaned.push(c);
+        }
+    }
+
+    let mut cleaned = trim_name(&collapse_spaces(&cleaned)).to_string();
+    if is_reserved(&cleaned) {
+        // "CON.txt" is reserved as well, so the stem has to change.
+        let stem_end = cleaned.find('.').unwrap_or(cleaned.len());
+        cleaned.insert(stem_end, REPLACEMENT);
+    }
+
+    let truncated = trim_name(truncate_bytes(&cleaned, max_bytes)).to_string();
+    if truncated.is_empty() || truncated == "." || truncated == ".." {
+        return REPLACEMENT.to_string();
+    }
+    truncated
+}
+
+/// Cuts `s` to at most `max_bytes` without splitting a character.
+pub fn truncate_bytes(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn collapse_spaces(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut last_space = false;
+    for c in s.chars() {
+        if c == ' ' {
+            if !last_space {
+                out.push(c);
+            }
+            last_space = true;
+        } else {
+            out.push(c);
+            last_space = false;
+        }
+    }
+    out
+}
+
+// Windows silently strips trailing dots and spaces.
+fn trim_name(s: &str) -> &str {
+    s.trim_start_matches(' ').trim_end_matches(&[' ', '.'][..])
+}
+
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// `title` as a name on disk that no other title ends up with. When
+/// sanitizing changed the title, titles that differed in what was lost
+/// could share the result, so `discriminator` is appended to tell them
+/// apart. Titles the sanitizer leaves alone are kept as they are.
+pub fn distinct_filename(title: &str, discriminator: &str) -> String {
+    let name = sanitize_filename(title);
+    if name == title {
+        return name;
+    }
+    let suffix = format!(" ({})", sanitize_filename(discriminator));
+    let limit = MAX_FILENAME_BYTES.saturating_sub(suffix.len());
+    format!("{}{}", sanitize_with_limit(title, limit), suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_reserved_characters() {
+        assert_eq!(sanitize_filename("Fate/Zero"), "Fate_Zero");
+        assert_eq!(sanitize_filename("What? <Really>"), "What_ _Really_");
+        assert_eq!(sanitize_filename("tab\there"), "tab_here");
+    }
+
+    #[test]
+    fn collapses_and_trims_spaces_and_dots() {
+        assert_eq!(sanitize_filename("  Spaced   out  "), "Spaced out");
+        assert_eq!(sanitize_filename("Trailing..."), "Trailing");
+        assert_eq!(sanitize_filename("..."), "_");
+        assert_eq!(sanitize_filename(""), "_");
+    }
+
+    #[test]
+    fn changes_the_stem_of_reserved_names() {
+        assert_eq!(sanitize_filename("con"), "con_");
+        assert_eq!(sanitize_filename("CON.txt"), "CON_.txt");
+        assert_eq!(sanitize_filename("LPT1 .mp4"), "LPT1 _.mp4");
+        assert_eq!(sanitize_filename("Console"), "Console");
+    }
+
+    #[test]
+    fn normalizes_to_nfc() {
+        assert_eq!(sanitize_filename("Pok\u{e9}mon"), "Pok\u{e9}mon");
+        assert_eq!(sanitize_filename("Poke\u{301}mon"), "Pok\u{e9}mon");
+    }
+
+    #[test]
+    fn truncates_on_character_boundaries() {
+        assert_eq!(sanitize_with_limit("abcdef", 4), "abcd");
+        assert_eq!(sanitize_with_limit("日本語", 7), "日本");
+        assert_eq!(sanitize_with_limit("ab cd", 3), "ab");
+        assert_eq!(
+            sanitize_filename(&"x".repeat(300)).len(),
+            MAX_FILENAME_BYTES
+        );
+    }
+
+    #[test]
+    fn distinct_names_for_titles_that_lost_characters() {
+        assert_eq!(distinct_filename("Mushishi", "12"), "Mushishi");
+        assert_eq!(distinct_filename("Foo?", "1"), "Foo_ (1)");
+        assert_eq!(distinct_filename("Foo*", "2"), "Foo_ (2)");
+        assert_eq!(distinct_filename("Foo_", "3"), "Foo_");
+        assert_eq!(
+            distinct_filename("Foo?", "1"),
+            distinct_filename("Foo?", "1")
+        );
+    }
+
+    #[test]
+    fn distinct_names_stay_within_the_limit() {
+        let name = distinct_filename(&"x?".repeat(200), "1234");
+        assert_eq!(name.len(), MAX_FILENAME_BYTES);
+        assert!(name.ends_with("_ (1234)"));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::pretty_bytes::convert;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// When downloads may run. Without any windows they always may.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Schedule {
+    pub windows: Vec<Window>,
+}
+
+/// A daily time span downloads are allowed in, e.g. 22:00 to 06:00 on
+/// weekdays. A window ending before it starts runs into the next day.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Window {
+    /// Days the window starts on, every day when empty.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    #[serde(deserialize_with = "time_from_str", serialize_with = "time_to_str")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "time_from_str", serialize_with = "time_to_str")]
+    pub end: NaiveTime,
+    /// Bandwidth limit in KiB per second.
+    #[serde(default)]
+    pub max_rate: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// No windows configured.
+    Always,
+    Open {
+        until: NaiveDateTime,
+        /// Bytes per second.
+        limit: Option<u64>,
+    },
+    Closed {
+        opens: Option<NaiveDateTime>,
+    },
+}
+
+impl Schedule {
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// The first window that is open at `now` decides the limit.
+    pub fn status(&self, now: NaiveDateTime) -> Status {
+        if self.windows.is_empty() {
+            return Status::Always;
+        }
+
+        let open = self.windows.iter().find_map(|window| {
+            // Yesterday's window may still be running.
+            (-1..=0)
+                .filter_map(|days| window.span(now.date() + Duration::days(days)))
+                .find(|(start, end)| *start <= now && now < *end)
+                .map(|(_, end)| (window, end))
+        });
+        if let Some((window, until)) = open {
+            return Status::Open {
+                until,
+                limit: window.max_rate.map(|rate| rate * 1024),
+            };
+        }
+
+        let opens = self
+            .windows
+            .iter()
+            .flat_map(|window| {
+                (0..=7).filter_map(move |days| window.span(now.date() + Duration::days(days)))
+            })
+            .map(|(start, _)| start)
+            .filter(|start| *start > now)
+            .min();
+        Status::Closed { opens }
+    }
+}
+
+impl Window {
+    // Start and end of the window starting on `date`, if it does.
+    fn span(&self, date: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        if !self.days.is_empty() && !self.days.contains(&date.weekday()) {
+            return None;
+        }
+        let start = date.and_time(self.start);
+        let mut end = date.and_time(self.end);
+        if end <= start {
+            end += Duration::days(1);
+        }
+        Some((start, end))
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Always => write!(f, "allowed"),
+            Status::Open { until, limit } => {
+                write!(f, "allowed until {}", until.format("%a %H:%M"))?;
+                match limit {
+                    Some(limit) => write!(f, ", max {}/s", convert(*limit)),
+                    None => Ok(()),
+                }
+            }
+            Status::Closed { opens: Some(opens) } => {
+                write!(f, "paused until {}", opens.format("%a %H:%M"))
+            }
+            Status::Closed { opens: None } => write!(f, "paused, no window ahead"),
+        }
+    }
+}
+
+// Times are written as "22:00", seconds are optional.
+fn time_from_str<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(&s, "%H:%M:%S"))
+        .map_err(|_| D::Error::custom(format!("expected a time like 22:00, got '{}'", s)))
+}
+
+fn time_to_str<S>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&time.format("%H:%M").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // June 2020 starts on a Monday.
+        NaiveDate::from_ymd(2020, 6, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn always_without_windows() {
+        assert_eq!(Schedule::default().status(at(1, 12, 0)), Status::Always);
+    }
+
+    #[test]
+    fn open_inside_a_window_with_its_limit() {
+        let schedule: Schedule = serde_json::from_str(
+            r#"{"windows": [{"start": "09:00", "end": "17:00", "max_rate": 100}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            schedule.status(at(1, 12, 0)),
+            Status::Open {
+                until: at(1, 17, 0),
+                limit: Some(100 * 1024)
+            }
+        );
+        assert_eq!(
+            schedule.status(at(1, 17, 0)),
+            Status::Closed {
+                opens: Some(at(2, 9, 0))
+            }
+        );
+    }
+
+    #[test]
+    fn windows_past_midnight_run_into_the_next_day() {
+        let schedule: Schedule =
+            serde_json::from_str(r#"{"windows": [{"start": "22:00", "end": "06:00:00"}]}"#)
+                .unwrap();
+        assert_eq!(
+            schedule.status(at(2, 3, 0)),
+            Status::Open {
+                until: at(2, 6, 0),
+                limit: None
+            }
+        );
+        assert_eq!(
+            schedule.status(at(2, 21, 59)),
+            Status::Closed {
+                opens: Some(at(2, 22, 0))
+            }
+        );
+    }
+
+    #[test]
+    fn days_are_the_days_a_window_starts_on() {
+        let schedule: Schedule = serde_json::from_str(
+            r#"{"windows": [{"days": ["Sat"], "start": "23:00", "end": "02:00"}]}"#,
+        )
+        .unwrap();
+        // Sunday night still belongs to Saturday's window.
+        assert_eq!(
+            schedule.status(at(7, 1, 0)),
+            Status::Open {
+                until: at(7, 2, 0),
+                limit: None
+            }
+        );
+        assert_eq!(
+            schedule.status(at(1, 12, 0)),
+            Status::Closed {
+                opens: Some(at(6, 23, 0))
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_times() {
+        let result: Result<Schedule, _> =
+            serde_json::from_str(r#"{"windows": [{"start": "10pm", "end": "06:00"}]}"#);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::{
+    normalize::{normalize_query, Normalized},
+    query::{FilterContext, Query},
+    types::{Anime, ID},
+    ui::Message,
+};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{sync::mpsc::Sender, task};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MatchMode {
+    #[default]
+    Fuzzy,
+    Prefix,
+}
+
+impl MatchMode {
+    pub fn toggle(self) -> Self {
+        match self {
+            MatchMode::Fuzzy => MatchMode::Prefix,
+            MatchMode::Prefix => MatchMode::Fuzzy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    /// Minimum skim score a title needs to be part of the result.
+    pub threshold: i64,
+    pub mode: MatchMode,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 10,
+            mode: MatchMode::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Field {
+    #[default]
+    Title,
+    AltTitle,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Match {
+    pub anime: Anime,
+    pub score: i64,
+    pub field: Field,
+    /// Char positions in `field` that matched the query.
+    pub indices: Vec<usize>,
+    /// Shown in the recent shows section rather than as a search result.
+    pub recent: bool,
+}
+
+impl From<Anime> for Match {
+    fn from(anime: Anime) -> Self {
+        Self {
+            anime,
+            ..Default::default()
+        }
+    }
+}
+
+impl Match {
+    /// Text shown in the anime list together with the char positions to
+    /// highlight. Alt title matches are shown after the title.
+    pub fn label(&self) -> (String, Vec<usize>) {
+        match (self.field, &self.anime.alt_title) {
+            (Field::AltTitle, Some(alt_title)) => {
+                let shift = self.anime.title.chars().count() + 2;
+                (
+                    format!("{} [{}]", self.anime.title, alt_title),
+                    self.indices.iter().map(|i| i + shift).collect(),
+                )
+            }
+            _ => (self.anime.title.clone(), self.indices.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    anime: Anime,
+    title: Normalized,
+    alt_title: Option<Normalized>,
+}
+
+/// The anime list with every title normalized up front, so a keystroke only
+/// has to run the matcher.
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    entries: Vec<Entry>,
+}
+
+impl SearchIndex {
+    pub fn new(animes: &[Anime]) -> Self {
+        let entries = animes
+            .iter()
+            .map(|anime| Entry {
+                anime: anime.clone(),
+                title: Normalized::new(&anime.title),
+                alt_title: anime.alt_title.as_deref().map(Normalized::new),
+            })
+            .collect();
+        Self { entries }
+    }
+
+    pub fn all(&self) -> Vec<Match> {
+        self.entries
+            .iter()
+            .map(|entry| Match::from(entry.anime.clone()))
+            .collect()
+    }
+
+    pub fn update<F>(&mut self, id: ID, f: F)
+    where
+        F: Fn(&mut Anime),
+    {
+        for entry in self.entries.iter_mut().filter(|e| e.anime.id == id) {
+            f(&mut entry.anime);
+        }
+    }
+
+    /// Drops everything the filters of `query` reject and matches its text
+    /// against title and alt title, best match first. Only the entries in
+    /// `candidates` are looked at when given. Returns every entry that matched
+    /// at all (before the threshold) next to the result, or `None` if
+    /// `cancelled` fired halfway.
+    pub fn search<C>(
+        &self,
+        query: &Query,
+        options: &SearchOptions,
+        context: &FilterContext,
+        candidates: Option<&[usize]>,
+        cancelled: C,
+    ) -> Option<(Vec<usize>, Vec<Match>)>
+    where
+        C: Fn() -> bool,
+    {
+        if query.is_empty() {
+            return Some(((0..self.entries.len()).collect(), self.all()));
+        }
+        let text = normalize_query(&query.text);
+
+        let all: Vec<usize>;
+        let candidates = match candidates {
+            Some(candidates) => candidates,
+            None => {
+                all = (0..self.entries.len()).collect();
+                &all
+            }
+        };
+
+        let matcher = SkimMatcherV2::default().ignore_case();
+        let mut matched = Vec::new();
+        let mut matches = Vec::new();
+
+        for (n, &idx) in candidates.iter().enumerate() {
+            if n % 256 == 0 && cancelled() {
+                return None;
+            }
+            let entry = &self.entries[idx];
+            if !query.matches_filters(&entry.anime, context) {
+                continue;
+            }
+            if text.is_empty() {
+                matched.push(idx);
+                matches.push(Match::from(entry.anime.clone()));
+                continue;
+            }
+            let fields = std::iter::once((Field::Title, &entry.title)).chain(
+                entry
+                    .alt_title
+                    .as_ref()
+                    .map(|alt_title| (Field::AltTitle, alt_title)),
+            );
+
+            let best = fields
+                .filter_map(|(field, field_text)| {
+                    let (score, indices) = match options.mode {
+                        MatchMode::Fuzzy => matcher.fuzzy_indices(&field_text.text, &text)?,
+                        MatchMode::Prefix => prefix_match(&field_text.text, &text)?,
+                    };
+                    Some((field, field_text, score, indices))
+                })
+                .max_by_key(|(_, _, score, _)| *score);
+
+            if let Some((field, field_text, score, indices)) = best {
+                matched.push(idx);
+                if options.mode == MatchMode::Prefix || score >= options.threshold {
+                    matches.push(Match {
+                        anime: entry.anime.clone(),
+                        score,
+                        field,
+                        indices: field_text.original_indices(&indices),
+                        recent: false,
+                    });
+                }
+            }
+        }
+
+        // Stable, so equal scores keep the catalog order.
+        matches.sort_by_key(|m| Reverse(m.score));
+        Some((matched, matches))
+    }
+}
+
+// Shorter titles rank higher, so an exact hit ends up first.
+fn prefix_match(text: &str, query: &str) -> Option<(i64, Vec<usize>)> {
+    if !text.starts_with(query) {
+        return None;
+    }
+    let matched = query.chars().count();
+    let total = text.chars().count().max(1);
+    let score = (matched * 1000 / total) as i64;
+    Some((score, (0..matched).collect()))
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOutcome {
+    pub generation: u64,
+    pub query: Query,
+    pub options: SearchOptions,
+    pub candidates: Arc<Vec<usize>>,
+    pub matches: Vec<Match>,
+}
+
+/// Runs searches on the blocking pool. Starting a new search cancels the one
+/// in flight, and a query that extends the previous one only looks at what
+/// the previous one matched.
+#[derive(Debug, Clone, Default)]
+pub struct SearchEngine {
+    index: Arc<SearchIndex>,
+    generation: Arc<AtomicU64>,
+    context: Arc<FilterContext>,
+    last: Option<(Query, SearchOptions, Arc<Vec<usize>>)>,
+}
+
+impl SearchEngine {
+    pub fn new(animes: &[Anime]) -> Self {
+        Self {
+            index: Arc::new(SearchIndex::new(animes)),
+            ..Default::default()
+        }
+    }
+
+    pub fn all(&self) -> Vec<Match> {
+        self.index.all()
+    }
+
+    pub fn update<F>(&mut self, id: ID, f: F)
+    where
+        F: Fn(&mut Anime),
+    {
+        Arc::make_mut(&mut self.index).update(id, f);
+        self.last = None;
+    }
+
+    pub fn set_context(&mut self, context: FilterContext) {
+        self.context = Arc::new(context);
+        self.last = None;
+    }
+
+    pub fn context(&self) -> &FilterContext {
+        &self.context
+    }
+
+    pub fn start(&mut self, query: Query, options: &SearchOptions, mut sender: Sender<Message>) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let candidates = match &self.last {
+            Some((last_query, last_options, candidates))
+                if last_options == options
+                    && last_query.filters == query.filters
+                    && normalize_query(&query.text)
+                        .starts_with(&normalize_query(&last_query.text)) =>
+            {
+                Some(candidates.clone())
+            }
+            _ => None,
+        };
+
+        let index = self.index.clone();
+        let context = self.context.clone();
+        let current = self.generation.clone();
+        let options = options.clone();
+        tokio::spawn(async move {
+            let search_query = query.clone();
+            let search_options = options.clone();
+            let result = task::spawn_blocking(move || {
+                let cancelled = || current.load(Ordering::SeqCst) != generation;
+                let candidates = candidates.as_ref().map(|c| c.as_slice());
+                index.search(
+                    &search_query,
+                    &search_options,
+                    &context,
+                    candidates,
+                    cancelled,
+                )
+            })
+            .await;
+
+            if let Ok(Some((candidates, matches))) = result {
+                let outcome = SearchOutcome {
+                    generation,
+                    query,
+                    options,
+                    candidates: Arc::new(candidates),
+                    matches,
+                };
+                let _ = sender.send(Message::SearchFinished(outcome)).await;
+            }
+        });
+    }
+
+    /// Returns the matches if `outcome` belongs to the latest search.
+    pub fn finish(&mut self, outcome: SearchOutcome) -> Option<Vec<Match>> {
+        if outcome.generation != self.generation.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.last = Some((outcome.query, outcome.options, outcome.candidates));
+        Some(outcome.matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Filter;
+
+    fn anime(id: ID, title: &str, alt_title: Option<&str>) -> Anime {
+        Anime {
+            id,
+            title: title.to_string(),
+            alt_title: alt_title.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn index() -> SearchIndex {
+        SearchIndex::new(&[
+            anime(1, "Shingeki no Kyojin", Some("Attack on Titan")),
+            anime(2, "Shingeki no Kyojin Season 2", None),
+            anime(3, "Mushishi", None),
+            anime(4, "Shoujo Shuumatsu Ryokou", Some("Girls' Last Tour")),
+        ])
+    }
+
+    fn search(index: &SearchIndex, query: &str, mode: MatchMode) -> Vec<ID> {
+        let options = SearchOptions {
+            mode,
+            ..Default::default()
+        };
+        let query = Query::parse(query).unwrap();
+        let (_, matches) = index
+            .search(&query, &options, &FilterContext::default(), None, || false)
+            .unwrap();
+        matches.iter().map(|m| m.anime.id).collect()
+    }
+
+    #[test]
+    fn an_empty_query_lists_everything_in_order() {
+        assert_eq!(search(&index(), "", MatchMode::Fuzzy), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn prefix_matches_rank_shorter_titles_first() {
+        assert_eq!(search(&index(), "shingeki", MatchMode::Prefix), vec![1, 2]);
+        assert_eq!(
+            search(&index(), "kyojin", MatchMode::Prefix),
+            Vec::<ID>::new()
+        );
+        assert_eq!(search(&index(), "shojo", MatchMode::Prefix), vec![4]);
+    }
+
+    #[test]
+    fn fuzzy_matches_alt_titles_and_highlights_them() {
+        let index = index();
+        let query = Query::parse("titan").unwrap();
+        let (_, matches) = index
+            .search(
+                &query,
+                &SearchOptions::default(),
+                &FilterContext::default(),
+                None,
+                || false,
+            )
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].field, Field::AltTitle);
+        let (label, indices) = matches[0].label();
+        assert_eq!(label, "Shingeki no Kyojin [Attack on Titan]");
+        let highlighted: String = label
+            .chars()
+            .enumerate()
+            .filter(|(i, _)| indices.contains(i))
+            .map(|(_, c)| c)
+            .collect();
+        assert_eq!(highlighted, "Titan");
+    }
+
+    #[test]
+    fn scores_below_the_threshold_are_dropped() {
+        let index = index();
+        let query = Query::parse("mshi").unwrap();
+        let strict = SearchOptions {
+            threshold: i64::MAX,
+            ..Default::default()
+        };
+        let (matched, matches) = index
+            .search(&query, &strict, &FilterContext::default(), None, || false)
+            .unwrap();
+        assert_eq!(matched, vec![2]);
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn filters_apply_before_matching() {
+        let mut context = FilterContext::default();
+        context.followed.insert(2);
+        let query = Query::parse("followed:yes shingeki").unwrap();
+        let (_, matches) = index()
+            .search(&query, &SearchOptions::default(), &context, None, || false)
+            .unwrap();
+        let ids: Vec<ID> = matches.iter().map(|m| m.anime.id).collect();
+        assert_eq!(ids, vec![2]);
+        assert_eq!(query.filters, vec![Filter::Followed(true)]);
+    }
+
+    #[test]
+    fn only_candidates_are_looked_at() {
+        let query = Query::parse("shingeki").unwrap();
+        let (matched, _) = index()
+            .search(
+                &query,
+                &SearchOptions::default(),
+                &FilterContext::default(),
+                Some(&[1, 2]),
+                || false,
+            )
+            .unwrap();
+        assert_eq!(matched, vec![1]);
+    }
+
+    #[test]
+    fn cancelled_searches_return_nothing() {
+        let query = Query::parse("shingeki").unwrap();
+        let result = index().search(
+            &query,
+            &SearchOptions::default(),
+            &FilterContext::default(),
+            None,
+            || true,
+        );
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn outdated_outcomes_are_ignored() {
+        let mut engine = SearchEngine::new(&[anime(3, "Mushishi", None)]);
+        let outcome = |generation| SearchOutcome {
+            generation,
+            query: Query::parse("mushi").unwrap(),
+            options: SearchOptions::default(),
+            candidates: Arc::new(vec![0]),
+            matches: engine.all(),
+        };
+        let (first, second) = (outcome(1), outcome(2));
+        engine.generation.store(2, Ordering::SeqCst);
+        assert!(engine.finish(first).is_none());
+        assert_eq!(engine.finish(second).map(|m| m.len()), Some(1));
+        assert!(engine.last.is_some());
+    }
+}
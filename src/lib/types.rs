@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Deserializer, Serialize};
 
 pub type ID = u64;
 
@@ -7,6 +8,89 @@ pub struct Anime {
     pub id: ID,
     pub title: String,
     pub alt_title: Option<String>,
+    pub slug: Option<Slug>,
+    pub hb_id: Option<ID>,
+    #[serde(default)]
+    pub hidden: u8,
+    pub mal_id: Option<ID>,
+    #[serde(default)]
+    pub season: u32,
+    #[serde(default, deserialize_with = "bool_from_int")]
+    pub ongoing: bool,
+    #[serde(default, deserialize_with = "lenient_datetime")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(default, deserialize_with = "lenient_datetime")]
+    pub updated_at: Option<NaiveDateTime>,
+    /// Not part of the anime list, filled in once the episodes have been fetched.
+    #[serde(default)]
+    pub episode_count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Hash, Eq, PartialEq)]
+pub struct Slug {
+    pub id: ID,
+    pub slug: String,
+    pub anime_id: ID,
+}
+
+impl Anime {
+    /// Slug used by the api, cache entries from before slugs were stored
+    /// fall back to guessing it from the title.
+    pub fn slug(&self) -> String {
+        match &self.slug {
+            Some(slug) => slug.slug.clone(),
+            None => clear_title(&self.title),
+        }
+    }
+}
+
+/// Guesses the api slug from a title, only used for cached animes without one.
+pub fn clear_title(s: &str) -> String {
+    s.trim()
+        .replace(&[' ', '\''][..], "-")
+        .replace(
+            &[
+                ' ', '~', '@', '#', '$', '&', '(', ')', '*', '!', '+', '=', ':', ';', ',', '.',
+                '?', '/', '\'',
+            ][..],
+            "",
+        )
+        .to_lowercase()
+}
+
+// The api sends flags as 0/1, the cache stores them as booleans.
+fn bool_from_int<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Int(i64),
+    }
+
+    Ok(match Flag::deserialize(deserializer)? {
+        Flag::Bool(b) => b,
+        Flag::Int(i) => i != 0,
+    })
+}
+
+// A timestamp we can't parse should not make the whole anime list unusable.
+fn lenient_datetime<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    Ok(s.and_then(|s| {
+        [
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%dT%H:%M:%S%.fZ",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&s, format).ok())
+    }))
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
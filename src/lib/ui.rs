@@ -1,20 +1,38 @@
 use crate::{
-    api::{fetch_all_animes, fetch_anime, fetch_video},
-    datastore::{AnimeStore, ANIME_PATH},
-    types::{Anime, Animes, DownloadInfo, Episode},
+    api::{
+        downloaded_animes, downloaded_episodes, episode_media, fetch_all_animes, fetch_anime,
+        fetch_video,
+    },
+    config::Config,
+    datastore::{AnimeStore, History, ANIME_PATH},
+    downloads::DownloadHistory,
+    follow::{fetch_episodes, Follows, RefreshResult},
+    library::Library,
+    player::{play, timestamp, Playback},
+    pretty_bytes::convert,
+    query::{FilterContext, ParseError, Query},
+    search::{Match, SearchEngine, SearchOutcome},
+    types::{Anime, Animes, DownloadInfo, Episode, ID},
     ui_components::{
-        anime::AnimeList, episodes::EpisodeList, notifications::Notification, progress::Progress,
-        search::Search,
+        anime::AnimeList,
+        details::Details,
+        episodes::EpisodeList,
+        history::HistoryView,
+        library::{LibraryFocus, LibraryView},
+        notifications::Notification,
+        progress::{DownloadProgress, Progress},
+        search::{Edit, Search},
     },
+    watched::Watched,
 };
+use chrono::{Local, NaiveDateTime};
 use crossterm::{
-    event::{Event, EventStream, KeyCode, KeyEvent},
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{enable_raw_mode, EnterAlternateScreen},
 };
 use futures::{future::FutureExt, select, StreamExt};
 use futures_timer::Delay;
-use fuzzy_matcher::skim::SkimMatcherV2;
 use std::{
     collections::VecDeque,
     error::Error,
@@ -22,7 +40,10 @@ use std::{
     path::Path,
     time::Duration,
 };
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::{
+    sync::mpsc::{channel, Receiver, Sender},
+    task,
+};
 use tui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
@@ -39,26 +60,56 @@ pub struct App {
     ui: Ui,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum SelectMode {
+    #[default]
     Anime,
     Episode,
 }
 
-impl Default for SelectMode {
-    fn default() -> Self {
-        SelectMode::Anime
-    }
+/// Which part of the anime view receives text input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Focus {
+    #[default]
+    Search,
+    List,
 }
 
+/// Browsing the catalog, what is on disk, or what was downloaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum View {
+    #[default]
+    Browse,
+    Library,
+    History,
+}
+
+/// Rows moved by PageUp and PageDown.
+const PAGE_SIZE: isize = 10;
+
+/// Seconds an episode has to play before stopping it saves the position.
+const MIN_RESUME_POSITION: f64 = 10.0;
+
 #[derive(Debug, Clone, Default)]
 pub struct State {
+    pub view: View,
     pub select_mode: SelectMode,
+    pub focus: Focus,
     pub animes: Animes,
     pub selected_anime: Anime,
-    pub query: String,
-    pub download_progress: Option<(u64, u64)>,
+    pub query_error: Option<ParseError>,
+    pub download_progress: Option<DownloadProgress>,
     pub download_queue: VecDeque<DownloadInfo>,
+    pub config: Config,
+    pub search: SearchEngine,
+    pub history: History,
+    pub follows: Follows,
+    pub watched: Watched,
+    /// Episodes of the selected anime that are on disk.
+    pub downloaded_episodes: Vec<i64>,
+    /// Select the first unwatched episode once the next anime is opened.
+    pub continue_watching: bool,
+    pub library: Library,
 }
 
 #[derive(Default, Debug)]
@@ -68,75 +119,514 @@ pub struct Ui {
     pub episodes: EpisodeList,
     pub anime: AnimeList,
     pub progress: Progress,
+    pub details: Details,
+    pub library: LibraryView,
+    pub history: HistoryView,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     KeyboardInput(KeyEvent),
+    /// Printable keys that arrived in one burst, most likely pasted text.
+    Paste(Vec<KeyEvent>),
+    /// The terminal changed size and needs a redraw.
+    Resized,
     AnimeSelected(Anime),
     EpisodeSelected(Episode),
     Download(DownloadMessage),
     Notification(Text<'static>),
+    SearchFinished(SearchOutcome),
+    FollowsChecked(RefreshResult),
+    PlaybackEnded(Anime, i64, Result<Playback, String>),
+    LibraryScanned(Result<Library, String>),
 }
 
 #[derive(Debug, Clone)]
 pub enum DownloadMessage {
     Progress(u64, u64),
+    /// Segments of a playlist done, segments in total and bytes so far.
+    Segments(usize, usize, u64),
     Finished,
     Starting,
+    /// The download window closed, with when the next one opens.
+    Paused(Option<NaiveDateTime>),
+    Resumed,
+    /// The episode, or the queue when it starts, needs the first number of
+    /// bytes, the disk has the second free. Downloads wait until there is room.
+    OutOfSpace(u64, u64),
+    Failed(String),
+    /// Another twist is downloading the episode already, with why.
+    Skipped(String),
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
         let (sender, receiver) = channel::<Message>(50);
         Self {
             sender,
             receiver,
-            state: Default::default(),
+            state: State {
+                config,
+                history: History::load().unwrap_or_default(),
+                follows: Follows::load().unwrap_or_default(),
+                watched: Watched::load().unwrap_or_default(),
+                library: Library::load().unwrap_or_default(),
+                ..Default::default()
+            },
             ui: Default::default(),
         }
     }
 
-    pub fn query(&self) -> Animes {
-        let matcher = SkimMatcherV2::default();
+    /// Keeps the current results on a parse error, the error is shown in the
+    /// search box instead.
+    fn start_search(&mut self) {
+        match Query::parse(self.ui.search.query()) {
+            Ok(query) => {
+                self.state.query_error = None;
+                self.state
+                    .search
+                    .start(query, &self.state.config.search, self.sender.clone());
+            }
+            Err(e) => self.state.query_error = Some(e),
+        }
+    }
 
-        self.state
+    fn highlighted_anime(&self) -> Option<&Anime> {
+        match self.state.select_mode {
+            SelectMode::Anime => self
+                .ui
+                .anime
+                .state
+                .selected()
+                .and_then(|idx| self.ui.anime.items.get(idx))
+                .map(|m| &m.anime),
+            SelectMode::Episode => Some(&self.state.selected_anime),
+        }
+    }
+
+    /// Fetches the episode lists of all followed animes in the background.
+    fn check_follows(&self) {
+        let animes = self
+            .state
+            .follows
             .animes
             .iter()
-            .filter(|anime| {
-                if self.state.query.len() < 1 {
-                    return true;
+            .map(|f| f.anime.clone())
+            .collect();
+        let mut sender = self.sender.clone();
+        tokio::spawn(async move {
+            let result = fetch_episodes(animes).await;
+            let _ = sender.send(Message::FollowsChecked(result)).await;
+        });
+    }
+
+    async fn toggle_follow(&mut self) -> Result<(), Box<dyn Error>> {
+        let anime = match self.highlighted_anime() {
+            Some(anime) => anime.clone(),
+            None => return Ok(()),
+        };
+        let text = if self.state.follows.toggle(&anime) {
+            // The open episode list is a free baseline.
+            if self.state.select_mode == SelectMode::Episode {
+                self.state.follows.update(anime.id, &self.ui.episodes.items);
+            }
+            format!("Following {}", anime.title)
+        } else {
+            format!("No longer following {}", anime.title)
+        };
+        self.state.follows.save()?;
+        self.refresh_filter_context();
+
+        let text = Text::styled(text, Style::new().fg(Color::LightBlue));
+        self.sender.send(Message::Notification(text)).await?;
+        Ok(())
+    }
+
+    async fn notify_new_episodes(&mut self, id: ID, new: &[i64]) -> Result<(), Box<dyn Error>> {
+        let text = match self.state.follows.announcement(id, new) {
+            Some(text) => Text::styled(text, Style::new().fg(Color::LightGreen)),
+            None => return Ok(()),
+        };
+        self.sender.send(Message::Notification(text)).await?;
+        Ok(())
+    }
+
+    /// Opens the next followed anime with unwatched episodes at the first of
+    /// them.
+    async fn continue_watching(&mut self) -> Result<(), Box<dyn Error>> {
+        let follows = &self.state.follows.animes;
+        let current = follows
+            .iter()
+            .position(|f| f.anime.id == self.state.selected_anime.id)
+            .map_or(0, |i| i + 1);
+        let next = follows
+            .iter()
+            .cycle()
+            .skip(current)
+            .take(follows.len())
+            .find(|f| {
+                let numbers = f.known_episodes.iter().copied();
+                self.state
+                    .watched
+                    .first_unwatched(f.anime.id, numbers)
+                    .is_some()
+            });
+
+        match next {
+            Some(followed) => {
+                self.state.continue_watching = true;
+                let anime = followed.anime.clone();
+                self.sender.send(Message::AnimeSelected(anime)).await?;
+            }
+            None => {
+                let text = Text::styled(
+                    "Nothing left to watch in the followed animes",
+                    Style::new().fg(Color::LightBlue),
+                );
+                self.sender.send(Message::Notification(text)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn select_first_unwatched(&mut self) {
+        let episodes = &self.ui.episodes.items;
+        let numbers = episodes.iter().map(|e| e.number);
+        if let Some(number) = self
+            .state
+            .watched
+            .first_unwatched(self.state.selected_anime.id, numbers)
+        {
+            let idx = episodes.iter().position(|e| e.number == number);
+            self.ui.episodes.state.select(idx);
+        }
+    }
+
+    /// Plays the selected episode in mpv, from where it was left unless
+    /// `from_start`.
+    async fn play_selected(&mut self, from_start: bool) -> Result<(), Box<dyn Error>> {
+        let episode = match self.ui.episodes.state.selected() {
+            Some(idx) => self.ui.episodes.items[idx].clone(),
+            None => return Ok(()),
+        };
+        let anime = self.state.selected_anime.clone();
+        let media = episode_media(&anime, &episode)?;
+
+        let saved = self.state.watched.position(anime.id, episode.number);
+        let start = if from_start { None } else { saved };
+        let text = match start {
+            Some(start) => format!(
+                "Resuming episode {} at {}, P plays it from the start",
+                episode.number,
+                timestamp(start)
+            ),
+            None => format!("Playing episode {}", episode.number),
+        };
+        let text = Text::styled(text, Style::new().fg(Color::LightBlue));
+        self.sender.send(Message::Notification(text)).await?;
+
+        let options = self.state.config.player.clone();
+        let mut sender = self.sender.clone();
+        tokio::spawn(async move {
+            let playback = play(media, start, &options)
+                .await
+                .map_err(|e| e.to_string());
+            let message = Message::PlaybackEnded(anime, episode.number, playback);
+            let _ = sender.send(message).await;
+        });
+        Ok(())
+    }
+
+    async fn on_playback_ended(
+        &mut self,
+        anime: Anime,
+        number: i64,
+        playback: Playback,
+    ) -> Result<(), Box<dyn Error>> {
+        let watched = &mut self.state.watched;
+        let text = if playback.is_watched(self.state.config.player.watched_threshold) {
+            watched.set(anime.id, number, true);
+            watched.set_position(anime.id, number, None);
+            format!("Watched episode {} of {}", number, anime.title)
+        } else {
+            match playback.position.filter(|p| *p >= MIN_RESUME_POSITION) {
+                Some(position) => {
+                    watched.set_position(anime.id, number, Some(position));
+                    format!("Episode {} stopped at {}", number, timestamp(position))
                 }
-                let title: &str = &anime.title;
-                return match matcher.simple_match(
-                    &title.clone(),
-                    &self.state.query.clone(),
-                    false,
-                    true,
-                ) {
-                    Some((m, _)) if m > 10 => true,
-                    _ => {
-                        let alt_title: &str = &anime.alt_title.clone().unwrap_or_default();
-                        return match matcher.simple_match(
-                            alt_title,
-                            &self.state.query.clone(),
-                            false,
-                            true,
-                        ) {
-                            Some((m2, _)) if m2 > 10 => true,
-                            _ => false,
-                        };
-                    }
-                };
-            })
-            .cloned()
-            .collect()
+                None => return Ok(()),
+            }
+        };
+        watched.save()?;
+
+        let text = Text::styled(text, Style::new().fg(Color::LightBlue));
+        self.sender.send(Message::Notification(text)).await?;
+        Ok(())
+    }
+
+    /// `w` and `u` mark the selected episode watched or unwatched, `W` and `U`
+    /// every episode up to it and with Ctrl held all of them.
+    fn mark_watched(&mut self, key: char, ctrl: bool) -> Result<(), Box<dyn Error>> {
+        let id = self.state.selected_anime.id;
+        let episodes = &self.ui.episodes.items;
+        let selected = self.ui.episodes.state.selected();
+        let watched = key.eq_ignore_ascii_case(&'w');
+
+        if ctrl {
+            self.state.watched.set_all(id, episodes, watched);
+        } else if let Some(episode) = selected.and_then(|i| episodes.get(i)) {
+            if key.is_ascii_uppercase() {
+                self.state
+                    .watched
+                    .set_up_to(id, episodes, episode.number, watched);
+            } else {
+                self.state.watched.set(id, episode.number, watched);
+            }
+        }
+        self.state.watched.save()
+    }
+
+    /// Rescans the download root in the background.
+    fn scan_library(&mut self) {
+        self.ui.library.scanning = true;
+        let mut library = self.state.library.clone();
+        let animes = self.state.animes.clone();
+        let mut sender = self.sender.clone();
+        tokio::spawn(async move {
+            let scanned = task::spawn_blocking(move || library.scan(&animes).map(|_| library))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|scanned| scanned.map_err(|e| e.to_string()));
+            let _ = sender.send(Message::LibraryScanned(scanned)).await;
+        });
+    }
+
+    async fn show_history(&mut self) -> Result<(), Box<dyn Error>> {
+        match DownloadHistory::load() {
+            Ok(history) => self.ui.history.replace_records(history.since(None)),
+            Err(e) => {
+                let text = Text::styled(
+                    format!("Could not read the download history: {}", e),
+                    Style::new().fg(Color::Red),
+                );
+                self.sender.send(Message::Notification(text)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_history_key(&mut self, msg: KeyEvent) -> Result<(), Box<dyn Error>> {
+        let records = &mut self.ui.history.records;
+        match msg.code {
+            KeyCode::Up => records.previous(),
+            KeyCode::Down => records.next(),
+            KeyCode::PageUp => records.jump(-PAGE_SIZE),
+            KeyCode::PageDown => records.jump(PAGE_SIZE),
+            KeyCode::Home => records.first(),
+            KeyCode::End => records.last(),
+            KeyCode::Esc => self.state.view = View::Browse,
+            KeyCode::Char('r') => {
+                let record = records
+                    .state
+                    .selected()
+                    .and_then(|idx| records.items.get(idx))
+                    .cloned();
+                let anime = record.as_ref().and_then(|record| {
+                    let animes = &self.state.animes;
+                    animes
+                        .iter()
+                        .find(|anime| anime.id == record.anime)
+                        .cloned()
+                });
+                if let (Some(record), Some(anime)) = (record, anime) {
+                    self.download_again(anime, record.episode).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn show_library(&mut self) {
+        let shows = self.state.library.shows(&self.state.animes);
+        self.ui.library.replace_shows(shows);
+    }
+
+    async fn on_library_key(&mut self, msg: KeyEvent) -> Result<(), Box<dyn Error>> {
+        let library = &mut self.ui.library;
+        match msg.code {
+            KeyCode::Up => library.step(false),
+            KeyCode::Down => library.step(true),
+            KeyCode::PageUp => library.jump(-PAGE_SIZE),
+            KeyCode::PageDown => library.jump(PAGE_SIZE),
+            KeyCode::Home => library.first(),
+            KeyCode::End => library.last(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Tab => {
+                library.focus = LibraryFocus::Files;
+            }
+            KeyCode::Left | KeyCode::BackTab => library.focus = LibraryFocus::Shows,
+            KeyCode::Esc if library.focus == LibraryFocus::Files => {
+                library.focus = LibraryFocus::Shows;
+            }
+            KeyCode::Esc => self.state.view = View::Browse,
+            KeyCode::Char('d') => self.delete_from_library().await?,
+            KeyCode::Char('r') => self.redownload().await?,
+            _ => {}
+        }
+        if msg.code != KeyCode::Char('d') {
+            self.ui.library.pending_delete.clear();
+        }
+        Ok(())
+    }
+
+    /// The first `d` marks the highlighted file, or all files of the show,
+    /// the second one deletes them.
+    async fn delete_from_library(&mut self) -> Result<(), Box<dyn Error>> {
+        let library = &self.ui.library;
+        let paths: Vec<_> = match library.focus {
+            LibraryFocus::Files => library
+                .selected_file()
+                .map(|f| f.path.clone())
+                .into_iter()
+                .collect(),
+            LibraryFocus::Shows => match library.selected_show() {
+                Some(show) => show.files.iter().map(|f| f.path.clone()).collect(),
+                None => Vec::new(),
+            },
+        };
+        if paths.is_empty() {
+            return Ok(());
+        }
+        if library.pending_delete != paths {
+            self.ui.library.pending_delete = paths;
+            return Ok(());
+        }
+
+        self.ui.library.pending_delete.clear();
+        for path in &paths {
+            self.state.library.remove(path)?;
+        }
+        self.state.library.save()?;
+        self.show_library();
+        self.on_files_changed();
+
+        let text = Text::styled(
+            format!("Deleted {} file(s)", paths.len()),
+            Style::new().fg(Color::LightBlue),
+        );
+        self.sender.send(Message::Notification(text)).await?;
+        Ok(())
+    }
+
+    /// Replaces the highlighted file with a fresh download.
+    async fn redownload(&mut self) -> Result<(), Box<dyn Error>> {
+        let library = &self.ui.library;
+        if library.focus != LibraryFocus::Files {
+            return Ok(());
+        }
+        let (anime, file) = match (library.selected_show(), library.selected_file()) {
+            (Some(show), Some(file)) => (show.anime.clone(), file.clone()),
+            _ => return Ok(()),
+        };
+        let (anime, number) = match (anime, file.episode) {
+            (Some(anime), Some(number)) => (anime, number),
+            _ => {
+                let text = Text::styled(
+                    "Orphaned files can't be downloaded again",
+                    Style::new().fg(Color::Red),
+                );
+                self.sender.send(Message::Notification(text)).await?;
+                return Ok(());
+            }
+        };
+
+        self.state.library.remove(&file.path)?;
+        self.state.library.save()?;
+        self.show_library();
+        self.download_again(anime, number).await
+    }
+
+    /// Queues the episode with a fresh source, it may have been re-uploaded.
+    async fn download_again(&mut self, anime: Anime, number: i64) -> Result<(), Box<dyn Error>> {
+        let episodes = fetch_anime(&anime).await?;
+        let text = match episodes.into_iter().find(|e| e.number == number) {
+            Some(episode) => {
+                self.queue_download(anime.clone(), episode);
+                Text::styled(
+                    format!("Downloading episode {} of {} again", number, anime.title),
+                    Style::new().fg(Color::LightBlue),
+                )
+            }
+            None => Text::styled(
+                format!(
+                    "Episode {} of {} is no longer available",
+                    number, anime.title
+                ),
+                Style::new().fg(Color::Red),
+            ),
+        };
+        self.sender.send(Message::Notification(text)).await?;
+        Ok(())
+    }
+
+    fn queue_download(&mut self, anime: Anime, episode: Episode) {
+        self.state
+            .download_queue
+            .push_back(DownloadInfo(anime, episode));
+
+        if self.state.download_queue.len() == 1 {
+            self.start_download();
+        }
+    }
+
+    /// Downloads the front of the queue, it stays there until it is done.
+    fn start_download(&self) {
+        let queue = &self.state.download_queue;
+        let DownloadInfo(anime, episode) = match queue.front() {
+            Some(info) => info.clone(),
+            None => return,
+        };
+        let queued = queue.len() - 1;
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            // Failures are reported through `DownloadMessage::Failed`.
+            let _ = fetch_video(&episode, &anime, queued, sender).await;
+        });
+    }
+
+    /// Something was downloaded or deleted.
+    fn on_files_changed(&mut self) {
+        self.ui.details.invalidate();
+        self.refresh_filter_context();
+        self.state.downloaded_episodes = downloaded_episodes(&self.state.selected_anime);
+        if self.state.view == View::Library {
+            self.scan_library();
+        }
+    }
+
+    /// Puts the recently opened animes in front of `matches`.
+    fn with_recent(&self, matches: Vec<Match>) -> Vec<Match> {
+        let recent = self.state.history.recent(&self.state.animes);
+        let recent = recent.into_iter().map(|anime| Match {
+            recent: true,
+            ..Match::from(anime.clone())
+        });
+        recent.chain(matches).collect()
+    }
+
+    fn refresh_filter_context(&mut self) {
+        let context = FilterContext {
+            downloaded: downloaded_animes(&self.state.animes),
+            followed: self.state.follows.ids(),
+        };
+        self.state.search.set_context(context);
     }
 
     pub async fn draw(
         &mut self,
         t: &mut Terminal<CrosstermBackend<Stdout>>,
     ) -> Result<(), Box<dyn Error>> {
+        let mut cursor = None;
         t.draw(|mut f| {
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
@@ -162,19 +652,74 @@ impl App {
                 .direction(Direction::Vertical)
                 .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
                 .split(chunk);
-            let (episode_chunk, notification_chunk) = (chunks[0], chunks[1]);
+            let (chunk, notification_chunk) = (chunks[0], chunks[1]);
+
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+                .split(chunk);
+            let (details_chunk, episode_chunk) = (chunks[0], chunks[1]);
 
+            let search_focused = self.search_focused();
             self.ui
                 .search
-                .draw(&mut f, search_chunk, &self.state.query)
+                .draw(
+                    &mut f,
+                    search_chunk,
+                    self.state.query_error.as_ref(),
+                    self.state.config.search.mode,
+                    search_focused,
+                )
                 .unwrap();
+            if search_focused {
+                cursor = Some(self.ui.search.cursor_position(search_chunk));
+            }
 
-            self.ui.anime.draw(&mut f, list_chunk).unwrap();
+            if self.state.view == View::Library {
+                self.ui.library.draw(&mut f, list_chunk, chunk).unwrap();
+            } else if self.state.view == View::History {
+                self.ui.history.draw(&mut f, list_chunk, chunk).unwrap();
+            } else {
+                let list_focused =
+                    self.state.focus == Focus::List && self.state.select_mode == SelectMode::Anime;
+                self.ui
+                    .anime
+                    .draw(&mut f, list_chunk, list_focused, &self.state.follows)
+                    .unwrap();
 
-            self.ui.episodes.draw(&mut f, episode_chunk).unwrap();
+                self.ui
+                    .episodes
+                    .draw(
+                        &mut f,
+                        episode_chunk,
+                        self.state.selected_anime.id,
+                        &self.state.watched,
+                        &self.state.downloaded_episodes,
+                    )
+                    .unwrap();
 
-            if let Some((a, b)) = self.state.download_progress {
-                self.ui.progress.draw(&mut f, download_chunk, a, b).unwrap();
+                let anime_list = &self.ui.anime;
+                let highlighted = match self.state.select_mode {
+                    SelectMode::Anime => anime_list
+                        .state
+                        .selected()
+                        .and_then(|idx| anime_list.items.get(idx))
+                        .map(|m| &m.anime),
+                    SelectMode::Episode => Some(&self.state.selected_anime),
+                };
+                self.ui
+                    .details
+                    .draw(&mut f, details_chunk, highlighted)
+                    .unwrap();
+            }
+
+            let schedule = &self.state.config.schedule;
+            if self.state.download_progress.is_some() || !schedule.is_empty() {
+                let status = schedule.status(Local::now().naive_local());
+                self.ui
+                    .progress
+                    .draw(&mut f, download_chunk, self.state.download_progress, status)
+                    .unwrap();
             }
 
             self.ui
@@ -183,6 +728,14 @@ impl App {
                 .unwrap();
         })?;
 
+        match cursor {
+            Some((x, y)) => {
+                t.show_cursor()?;
+                t.set_cursor(x, y)?;
+            }
+            None => t.hide_cursor()?,
+        }
+
         Ok(())
     }
 
@@ -191,33 +744,54 @@ impl App {
             Message::KeyboardInput(msg) => {
                 self.on_keyboard_message(msg).await?;
             }
-            Message::AnimeSelected(anime) => {
-                self.state.selected_anime = anime.clone();
+            Message::Paste(keys) => {
+                if self.search_focused() {
+                    let text: String = keys.iter().filter_map(pasted_char).collect();
+                    self.ui.search.paste(&text);
+                    self.start_search();
+                } else {
+                    // Fast typing looks the same, and outside the search
+                    // box every key is a command.
+                    for key in keys {
+                        self.on_keyboard_message(key).await?;
+                    }
+                }
+            }
+            // Every message is followed by a redraw.
+            Message::Resized => {}
+            Message::AnimeSelected(mut anime) => {
                 self.state.select_mode = SelectMode::Episode;
+                self.state.history.push_query(self.ui.search.query());
+                self.state.history.push_anime(anime.id);
+                let _ = self.state.history.save();
 
                 let episodes = fetch_anime(&anime).await?;
+                if self.state.follows.is_followed(anime.id) {
+                    let new = self.state.follows.update(anime.id, &episodes);
+                    self.notify_new_episodes(anime.id, &new).await?;
+                    self.state.follows.seen(anime.id);
+                    self.state.follows.save()?;
+                }
+                anime.episode_count = Some(episodes.len());
+                self.set_episode_count(anime.id, episodes.len());
+                self.state.selected_anime = anime.clone();
                 self.ui.episodes = EpisodeList::with_items(episodes);
+                self.state.downloaded_episodes = downloaded_episodes(&anime);
+                if std::mem::take(&mut self.state.continue_watching) {
+                    self.select_first_unwatched();
+                }
                 let text = Text::styled(
-                    format!("{:?}", anime.clone()),
+                    format!(
+                        "Found {} episodes of {}",
+                        anime.episode_count.unwrap_or_default(),
+                        anime.title
+                    ),
                     Style::new().fg(Color::LightBlue),
                 );
                 self.sender.send(Message::Notification(text)).await?;
             }
             Message::EpisodeSelected(episode) => {
-                self.state.download_queue.push_back(DownloadInfo(
-                    self.state.selected_anime.clone(),
-                    episode.clone(),
-                ));
-
-                if self.state.download_queue.len() == 1 {
-                    let DownloadInfo(anime, episode) =
-                        self.state.download_queue.front().unwrap().clone();
-
-                    let sender = self.sender.clone();
-                    tokio::spawn(async move {
-                        let _ = fetch_video(&episode, &anime, sender).await;
-                    });
-                }
+                self.queue_download(self.state.selected_anime.clone(), episode);
             }
             Message::Download(msg) => {
                 self.on_download_message(msg).await?;
@@ -225,50 +799,218 @@ impl App {
             Message::Notification(text) => {
                 self.ui.notification.update(text);
             }
+            Message::FollowsChecked(results) => {
+                for (id, episodes) in results {
+                    match episodes {
+                        Ok(episodes) => {
+                            let new = self.state.follows.update(id, &episodes);
+                            self.notify_new_episodes(id, &new).await?;
+                        }
+                        Err(e) => {
+                            let text = Text::styled(
+                                format!("Could not check anime {}: {}", id, e),
+                                Style::new().fg(Color::Red),
+                            );
+                            self.sender.send(Message::Notification(text)).await?;
+                        }
+                    }
+                }
+                self.state.follows.save()?;
+            }
+            Message::PlaybackEnded(anime, number, playback) => match playback {
+                Ok(playback) => self.on_playback_ended(anime, number, playback).await?,
+                Err(e) => {
+                    let text = Text::styled(
+                        format!("Could not play episode {}: {}", number, e),
+                        Style::new().fg(Color::Red),
+                    );
+                    self.sender.send(Message::Notification(text)).await?;
+                }
+            },
+            Message::LibraryScanned(library) => {
+                self.ui.library.scanning = false;
+                match library {
+                    Ok(library) => {
+                        self.state.library = library;
+                        self.state.library.save()?;
+                        self.show_library();
+                    }
+                    Err(e) => {
+                        let text = Text::styled(
+                            format!("Could not scan the library: {}", e),
+                            Style::new().fg(Color::Red),
+                        );
+                        self.sender.send(Message::Notification(text)).await?;
+                    }
+                }
+            }
+            Message::SearchFinished(outcome) => {
+                let empty = outcome.query.is_empty();
+                if let Some(matches) = self.state.search.finish(outcome) {
+                    let matches = if empty {
+                        self.with_recent(matches)
+                    } else {
+                        matches
+                    };
+                    self.ui.anime.replace_items(matches);
+                }
+            }
         };
         Ok(())
     }
 
+    fn set_episode_count(&mut self, id: ID, count: usize) {
+        let animes = self.state.animes.iter_mut();
+        let matches = self.ui.anime.items.iter_mut().map(|m| &mut m.anime);
+        for anime in animes.chain(matches).filter(|anime| anime.id == id) {
+            anime.episode_count = Some(count);
+        }
+        self.state
+            .search
+            .update(id, |anime| anime.episode_count = Some(count));
+    }
+
     async fn on_download_message(&mut self, msg: DownloadMessage) -> Result<(), Box<dyn Error>> {
         //self.ui.notification.update(Text::raw(format!("{:?}", msg))); // Tmp, may improve later.
         match msg {
             DownloadMessage::Progress(progress, total) => {
-                self.state.download_progress = Some((progress, total));
+                self.state.download_progress = Some(DownloadProgress::Bytes(progress, total));
+            }
+            DownloadMessage::Segments(done, total, bytes) => {
+                self.state.download_progress = Some(DownloadProgress::Segments(done, total, bytes));
             }
             DownloadMessage::Finished => {
                 let text = Text::styled("finished", Style::new().fg(Color::LightBlue));
                 self.state.download_progress = None;
+                self.on_files_changed();
                 self.state.download_queue.pop_front();
                 self.sender.send(Message::Notification(text)).await?;
-
-                if self.state.download_queue.len() > 0 {
-                    let DownloadInfo(anime, episode) =
-                        self.state.download_queue.pop_front().unwrap();
-                    let sender = self.sender.clone();
-                    tokio::spawn(async move {
-                        let _ = fetch_video(&episode, &anime, sender).await;
-                    });
+                if self.state.view == View::History {
+                    self.show_history().await?;
+                }
+                self.start_download();
+            }
+            DownloadMessage::Failed(error) => {
+                self.state.download_progress = None;
+                self.on_files_changed();
+                let text = match self.state.download_queue.pop_front() {
+                    Some(DownloadInfo(anime, episode)) => format!(
+                        "Download of {} episode {} failed: {}. Press r on it in the history (Ctrl-D) to try again.",
+                        anime.title, episode.number, error
+                    ),
+                    None => format!("Download failed: {}", error),
+                };
+                let text = Text::styled(text, Style::new().fg(Color::Red));
+                self.sender.send(Message::Notification(text)).await?;
+                if self.state.view == View::History {
+                    self.show_history().await?;
                 }
+                self.start_download();
+            }
+            DownloadMessage::Skipped(reason) => {
+                self.state.download_progress = None;
+                let text = match self.state.download_queue.pop_front() {
+                    Some(DownloadInfo(anime, episode)) => format!(
+                        "Skipped {} episode {}: {}",
+                        anime.title, episode.number, reason
+                    ),
+                    None => format!("Download skipped: {}", reason),
+                };
+                let text = Text::styled(text, Style::new().fg(Color::Yellow));
+                self.sender.send(Message::Notification(text)).await?;
+                self.start_download();
+            }
+            DownloadMessage::OutOfSpace(needed, free) => {
+                let reserve = self.state.config.disk.reserve;
+                let text = format!(
+                    "Downloads paused, {} are needed but only {} are free besides the {} MiB reserve. They continue once there is room.",
+                    convert(needed),
+                    convert(free),
+                    reserve
+                );
+                let text = Text::styled(text, Style::new().fg(Color::Red));
+                self.sender.send(Message::Notification(text)).await?;
             }
             DownloadMessage::Starting => {
                 let text = Text::styled("Starting", Style::new().fg(Color::LightBlue));
                 self.sender.send(Message::Notification(text)).await?;
             }
+            DownloadMessage::Paused(opens) => {
+                let text = match opens {
+                    Some(opens) => format!("Download paused until {}", opens.format("%a %H:%M")),
+                    None => String::from("Download paused, no download window ahead"),
+                };
+                let text = Text::styled(text, Style::new().fg(Color::Yellow));
+                self.sender.send(Message::Notification(text)).await?;
+            }
+            DownloadMessage::Resumed => {
+                let text = Text::styled("Download resumed", Style::new().fg(Color::LightBlue));
+                self.sender.send(Message::Notification(text)).await?;
+            }
         }
         Ok(())
     }
 
+    fn search_focused(&self) -> bool {
+        self.state.view == View::Browse
+            && self.state.focus == Focus::Search
+            && self.state.select_mode == SelectMode::Anime
+    }
+
     async fn on_keyboard_message(&mut self, msg: KeyEvent) -> Result<(), Box<dyn Error>> {
-        match msg.code {
-            KeyCode::Backspace => {
-                self.state.query.pop();
-                let anime = self.query();
-                self.ui.anime = AnimeList::with_items(anime);
+        let ctrl = msg.modifiers.contains(KeyModifiers::CONTROL);
+        if ctrl && msg.code == KeyCode::Char('l') {
+            self.state.view = match self.state.view {
+                View::Browse => {
+                    self.show_library();
+                    self.scan_library();
+                    View::Library
+                }
+                View::Library | View::History => View::Browse,
+            };
+            return Ok(());
+        }
+        if ctrl && msg.code == KeyCode::Char('d') {
+            self.state.view = match self.state.view {
+                View::History => View::Browse,
+                _ => {
+                    self.show_history().await?;
+                    View::History
+                }
+            };
+            return Ok(());
+        }
+        match self.state.view {
+            View::Library => return self.on_library_key(msg).await,
+            View::History => return self.on_history_key(msg).await,
+            View::Browse => {}
+        }
+
+        // Text input only goes to the search box while it has focus, the
+        // keys it doesn't use fall through to the list.
+        if self.search_focused() {
+            let edit = match msg.code {
+                KeyCode::Up | KeyCode::Down => {
+                    let history = self.state.history.queries.make_contiguous();
+                    self.ui.search.recall(history, msg.code == KeyCode::Up)
+                }
+                _ => self.ui.search.on_key(msg),
+            };
+            match edit {
+                Edit::Changed => {
+                    self.start_search();
+                    return Ok(());
+                }
+                Edit::Unchanged => return Ok(()),
+                Edit::Ignored => {}
             }
+        }
+
+        match msg.code {
             KeyCode::Enter => match self.state.select_mode {
                 SelectMode::Anime => {
                     if let Some(idx) = self.ui.anime.state.selected() {
-                        let anime = self.ui.anime.items.get(idx).unwrap();
+                        let anime = &self.ui.anime.items.get(idx).unwrap().anime;
                         self.sender
                             .send(Message::AnimeSelected(anime.clone()))
                             .await?;
@@ -283,8 +1025,6 @@ impl App {
                     };
                 }
             },
-            KeyCode::Left => {}
-            KeyCode::Right => {}
             KeyCode::Up => match self.state.select_mode {
                 SelectMode::Anime => {
                     self.ui.anime.previous();
@@ -301,22 +1041,63 @@ impl App {
                     self.ui.episodes.next();
                 }
             },
-            KeyCode::Home => {}
-            KeyCode::End => {}
-            KeyCode::PageUp => {}
-            KeyCode::PageDown => {}
-            KeyCode::Tab => {}
-            KeyCode::BackTab => {}
-            KeyCode::Delete => {}
-            KeyCode::Insert => {}
-            KeyCode::F(_) => {}
-            KeyCode::Char(c) => {
-                self.state.query.push(c);
-                let anime = self.query();
-                self.ui.anime = AnimeList::with_items(anime);
-            }
-            KeyCode::Null => {}
+            KeyCode::Home => match self.state.select_mode {
+                SelectMode::Anime => self.ui.anime.first(),
+                SelectMode::Episode => self.ui.episodes.first(),
+            },
+            KeyCode::End => match self.state.select_mode {
+                SelectMode::Anime => self.ui.anime.last(),
+                SelectMode::Episode => self.ui.episodes.last(),
+            },
+            KeyCode::PageUp => match self.state.select_mode {
+                SelectMode::Anime => self.ui.anime.jump(-PAGE_SIZE),
+                SelectMode::Episode => self.ui.episodes.jump(-PAGE_SIZE),
+            },
+            KeyCode::PageDown => match self.state.select_mode {
+                SelectMode::Anime => self.ui.anime.jump(PAGE_SIZE),
+                SelectMode::Episode => self.ui.episodes.jump(PAGE_SIZE),
+            },
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.state.focus = match self.state.focus {
+                    Focus::Search => Focus::List,
+                    Focus::List => Focus::Search,
+                };
+            }
+            KeyCode::Char('/') => {
+                self.state.focus = Focus::Search;
+            }
+            KeyCode::Char(c @ 'w')
+            | KeyCode::Char(c @ 'u')
+            | KeyCode::Char(c @ 'W')
+            | KeyCode::Char(c @ 'U')
+                if self.state.select_mode == SelectMode::Episode =>
+            {
+                self.mark_watched(c, ctrl)?;
+            }
+            KeyCode::Char(c @ 'p') | KeyCode::Char(c @ 'P')
+                if self.state.select_mode == SelectMode::Episode =>
+            {
+                self.play_selected(c == 'P').await?;
+            }
+            KeyCode::Char('c') => match self.state.select_mode {
+                SelectMode::Anime => self.continue_watching().await?,
+                SelectMode::Episode => self.select_first_unwatched(),
+            },
+            KeyCode::Char('f') => {
+                self.toggle_follow().await?;
+            }
+            KeyCode::Char('r') if ctrl => {
+                self.check_follows();
+            }
+            KeyCode::Char('t') if ctrl => {
+                let options = &mut self.state.config.search;
+                options.mode = options.mode.toggle();
+                self.start_search();
+            }
             KeyCode::Esc => match self.state.select_mode {
+                SelectMode::Anime if self.state.focus == Focus::Search => {
+                    self.state.focus = Focus::List;
+                }
                 SelectMode::Anime => {
                     self.on_exit()?;
                 }
@@ -324,6 +1105,7 @@ impl App {
                     self.state.select_mode = SelectMode::Anime;
                 }
             },
+            _ => {}
         };
 
         Ok(())
@@ -345,31 +1127,13 @@ impl App {
         terminal.hide_cursor()?;
         terminal.clear()?;
 
-        // Setup event listener for keypresses.
-        let mut key_sender = self.sender.clone();
-        // Maybe move to own function.
-        tokio::spawn(async move {
-            let mut reader = EventStream::new();
-            loop {
-                let mut delay = Delay::new(Duration::from_millis(1_000)).fuse();
-                let mut event = reader.next().fuse();
-
-                select! {
-                    _ = delay => {},
-                    maybe_event = event => {
-                        match maybe_event.unwrap().unwrap() {
-                            Event::Key(key) => { if let Err(_) =key_sender.send(Message::KeyboardInput(key)).await {
-                                println!("receiver dropped, while program where running!");
-                                return;
-                            } },
-                            _ => {}
-                        }
-                    }
-                }
-            }
-        });
+        listen_for_input(self.sender.clone());
 
-        self.ui.anime = AnimeList::with_items(self.state.animes.clone());
+        self.state.search = SearchEngine::new(&self.state.animes);
+        self.refresh_filter_context();
+        self.ui.anime = AnimeList::with_items(self.with_recent(self.state.search.all()));
+
+        self.check_follows();
 
         self.draw(&mut terminal).await?;
         // Initilize eventloop.
@@ -380,3 +1144,155 @@ impl App {
         Ok(())
     }
 }
+
+/// Forwards key presses to the event loop. Keys that arrive in one burst
+/// may be pasted text, see `burst_messages`.
+fn listen_for_input(mut sender: Sender<Message>) {
+    tokio::spawn(async move {
+        let mut reader = EventStream::new();
+        // What ended the last burst, it is handled next.
+        let mut pending = None;
+        loop {
+            let event = match pending.take() {
+                Some(event) => event,
+                None => {
+                    let maybe_event = {
+                        let mut delay = Delay::new(Duration::from_millis(1_000)).fuse();
+                        let mut event = reader.next().fuse();
+                        select! {
+                            _ = delay => None,
+                            maybe_event = event => maybe_event,
+                        }
+                    };
+                    match maybe_event {
+                        Some(event) => event.unwrap(),
+                        None => continue,
+                    }
+                }
+            };
+
+            let messages = match event {
+                Event::Key(key) => {
+                    let mut burst = vec![key];
+                    while let Some(Some(event)) = reader.next().now_or_never() {
+                        match event.unwrap() {
+                            Event::Key(key) => burst.push(key),
+                            event => {
+                                pending = Some(event);
+                                break;
+                            }
+                        }
+                    }
+                    burst_messages(burst)
+                }
+                Event::Resize(..) => vec![Message::Resized],
+                Event::Mouse(_) => continue,
+            };
+            for message in messages {
+                if sender.send(message).await.is_err() {
+                    println!("receiver dropped, while program where running!");
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Without bracketed paste a burst of printable keys is all there is to
+/// tell a paste by, so it has to fail safe: the keys are sent along and only
+/// the search box takes them as text, and Enter or Tab at the end still act
+/// as keys.
+fn burst_messages(mut burst: Vec<KeyEvent>) -> Vec<Message> {
+    let trailing = burst
+        .iter()
+        .rev()
+        .take_while(|key| matches!(key.code, KeyCode::Enter | KeyCode::Tab))
+        .count();
+    let typed = burst.len() - trailing;
+    if typed < 2 || !burst[..typed].iter().all(|key| pasted_char(key).is_some()) {
+        return burst.into_iter().map(Message::KeyboardInput).collect();
+    }
+    let after = burst.split_off(typed);
+    let mut messages = vec![Message::Paste(burst)];
+    messages.extend(after.into_iter().map(Message::KeyboardInput));
+    messages
+}
+
+fn pasted_char(key: &KeyEvent) -> Option<char> {
+    if key
+        .modifiers
+        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+    {
+        return None;
+    }
+    match key.code {
+        KeyCode::Char(c) => Some(c),
+        KeyCode::Enter => Some('\n'),
+        KeyCode::Tab => Some('\t'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(codes: &[KeyCode]) -> Vec<KeyEvent> {
+        codes.iter().map(|code| KeyEvent::from(*code)).collect()
+    }
+
+    fn kinds(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| match message {
+                Message::KeyboardInput(key) => format!("key {:?}", key.code),
+                Message::Paste(keys) => format!(
+                    "paste {}",
+                    keys.iter().filter_map(pasted_char).collect::<String>()
+                ),
+                message => format!("{:?}", message),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_keys_are_keys() {
+        let messages = burst_messages(keys(&[KeyCode::Char('w')]));
+        assert_eq!(kinds(&messages), vec!["key Char('w')"]);
+    }
+
+    #[test]
+    fn printable_bursts_may_be_pastes() {
+        let messages = burst_messages(keys(&[
+            KeyCode::Char('a'),
+            KeyCode::Char(' '),
+            KeyCode::Char('b'),
+        ]));
+        assert_eq!(kinds(&messages), vec!["paste a b"]);
+    }
+
+    #[test]
+    fn enter_and_tab_at_the_end_stay_keys() {
+        let messages = burst_messages(keys(&[
+            KeyCode::Char('o'),
+            KeyCode::Enter,
+            KeyCode::Char('k'),
+            KeyCode::Tab,
+            KeyCode::Enter,
+        ]));
+        assert_eq!(kinds(&messages), vec!["paste o\nk", "key Tab", "key Enter"]);
+        let messages = burst_messages(keys(&[KeyCode::Char('x'), KeyCode::Enter]));
+        assert_eq!(kinds(&messages), vec!["key Char('x')", "key Enter"]);
+    }
+
+    #[test]
+    fn bursts_with_commands_are_keys() {
+        let mut burst = keys(&[KeyCode::Char('a'), KeyCode::Down]);
+        burst.push(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        let messages = burst_messages(burst);
+        assert_eq!(
+            kinds(&messages),
+            vec!["key Char('a')", "key Down", "key Char('c')"]
+        );
+    }
+}
@@ -1,38 +1,138 @@
 use super::statefull_list::StatefulList;
-use crate::types::Anime;
+use crate::{follow::Follows, search::Match};
 
 use std::{error::Error, io::Stdout};
 use tui::{
     backend::CrosstermBackend,
+    buffer::Buffer,
     layout::Rect,
     style::{Color, Modifier, Style},
-    widgets::{Block, Borders, List, Text},
+    widgets::{Block, Borders, StatefulWidget, Widget},
     Frame,
 };
 
-pub type AnimeList = StatefulList<Anime>;
+pub type AnimeList = StatefulList<Match>;
 
 impl AnimeList {
+    /// Swaps in new search results, keeping the selected anime selected if it
+    /// is still part of them.
+    pub fn replace_items(&mut self, items: Vec<Match>) {
+        let selected = self
+            .state
+            .selected()
+            .and_then(|idx| self.items.get(idx))
+            .map(|m| m.anime.id);
+
+        self.items = items;
+        let idx = selected.and_then(|id| self.items.iter().position(|m| m.anime.id == id));
+        self.state.select(idx);
+    }
+
     pub fn draw(
         &mut self,
         painter: &mut Frame<CrosstermBackend<Stdout>>,
         chunk: Rect,
+        focused: bool,
+        follows: &Follows,
     ) -> Result<(), Box<dyn Error>> {
         let style = Style::default();
-        let items = self.items.iter().map(|i| Text::raw(i.title.clone()));
-
-        let items = List::new(items)
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title("Search result"),
-            )
-            .style(style)
-            .highlight_style(style.fg(Color::LightGreen).modifier(Modifier::BOLD))
-            .highlight_symbol(">");
+        let border = if focused { Color::Yellow } else { Color::White };
+        let list = MatchList {
+            items: &self.items,
+            selected: self.state.selected(),
+            follows,
+            block: Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(border))
+                .title("Search result"),
+            style,
+            highlight_style: style.fg(Color::LightGreen).modifier(Modifier::BOLD),
+            match_style: Style::default()
+                .fg(Color::Yellow)
+                .modifier(Modifier::UNDERLINED),
+        };
 
-        painter.render_stateful_widget(items, chunk, &mut self.state);
+        painter.render_stateful_widget(list, chunk, &mut self.offset);
 
         Ok(())
     }
 }
+
+/// Like `tui::widgets::List`, but every row can highlight the chars that
+/// matched the search query.
+struct MatchList<'a> {
+    items: &'a [Match],
+    selected: Option<usize>,
+    follows: &'a Follows,
+    block: Block<'a>,
+    style: Style,
+    highlight_style: Style,
+    match_style: Style,
+}
+
+impl<'a> StatefulWidget for MatchList<'a> {
+    type State = usize;
+
+    fn render(self, area: Rect, buf: &mut Buffer, offset: &mut usize) {
+        self.block.render(area, buf);
+        let area = self.block.inner(area);
+        if area.width < 2 || area.height < 1 {
+            return;
+        }
+        buf.set_background(area, self.style.bg);
+
+        let height = area.height as usize;
+        *offset = match self.selected {
+            Some(selected) if selected >= *offset + height => selected + 1 - height,
+            Some(selected) if selected < *offset => selected,
+            Some(_) => *offset,
+            None => 0,
+        };
+
+        let rows = self.items.iter().enumerate().skip(*offset).take(height);
+        for (row, (idx, item)) in rows.enumerate() {
+            let y = area.top() + row as u16;
+            let is_selected = self.selected == Some(idx);
+            let base = if is_selected {
+                self.highlight_style
+            } else {
+                self.style
+            };
+
+            let symbol = if is_selected { ">" } else { " " };
+            let (mut x, _) = buf.set_stringn(area.left(), y, symbol, 1, base);
+            if item.recent {
+                let width = area.right().saturating_sub(x) as usize;
+                x = buf.set_stringn(x, y, "↺ ", width, base.fg(Color::Cyan)).0;
+            }
+
+            let (label, indices) = item.label();
+            for (i, c) in label.chars().enumerate() {
+                let remaining = area.right().saturating_sub(x) as usize;
+                if remaining == 0 {
+                    break;
+                }
+                let style = if indices.contains(&i) {
+                    self.match_style.bg(base.bg)
+                } else {
+                    base
+                };
+                x = buf
+                    .set_stringn(x, y, c.encode_utf8(&mut [0; 4]), remaining, style)
+                    .0;
+            }
+
+            if self.follows.is_followed(item.anime.id) {
+                let width = area.right().saturating_sub(x) as usize;
+                x = buf.set_stringn(x, y, " ★", width, base.fg(Color::Yellow)).0;
+            }
+            let new = self.follows.new_count(item.anime.id);
+            if new > 0 {
+                let width = area.right().saturating_sub(x) as usize;
+                let badge = format!(" [{} new]", new);
+                let style = base.fg(Color::LightRed).modifier(Modifier::BOLD);
+                buf.set_stringn(x, y, &badge, width, style);
+            }
+        }
+    }
+}
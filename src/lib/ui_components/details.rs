@@ -0,0 +1,119 @@
+use crate::{
+    api::downloaded_episodes,
+    types::{Anime, ID},
+};
+use std::{error::Error, io::Stdout};
+use tui::{
+    backend::CrosstermBackend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::{Block, BorderType, Borders, Paragraph, Text},
+    Frame,
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct Details {
+    // Counting files on every redraw is too slow, so remember it per anime.
+    downloaded: Option<(ID, usize)>,
+}
+
+impl Details {
+    pub fn draw(
+        &mut self,
+        painter: &mut Frame<CrosstermBackend<Stdout>>,
+        chunk: Rect,
+        anime: Option<&Anime>,
+    ) -> Result<(), Box<dyn Error>> {
+        let block = Block::default()
+            .title_style(Style::default().fg(Color::Red))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::White))
+            .border_type(BorderType::Rounded)
+            .style(Style::default().bg(Color::Black))
+            .title("Details");
+
+        let text = match anime {
+            Some(anime) => self.rows(anime),
+            None => vec![Text::raw("No anime selected")],
+        };
+
+        let paragraph = Paragraph::new(text.iter()).block(block).wrap(true);
+        painter.render_widget(paragraph, chunk);
+
+        Ok(())
+    }
+
+    /// Forget the cached download count, e.g. after a download finished.
+    pub fn invalidate(&mut self) {
+        self.downloaded = None;
+    }
+
+    fn downloaded(&mut self, anime: &Anime) -> usize {
+        match self.downloaded {
+            Some((id, count)) if id == anime.id => count,
+            _ => {
+                let count = downloaded_episodes(anime).len();
+                self.downloaded = Some((anime.id, count));
+                count
+            }
+        }
+    }
+
+    fn rows(&mut self, anime: &Anime) -> Vec<Text<'static>> {
+        let label = Style::default().fg(Color::LightBlue);
+        let unknown = || String::from("-");
+
+        let mut rows = vec![Text::styled(
+            format!("{}\n", anime.title),
+            Style::default().modifier(Modifier::BOLD),
+        )];
+        if let Some(alt_title) = &anime.alt_title {
+            rows.push(Text::raw(format!("{}\n", alt_title)));
+        }
+        rows.push(Text::raw("\n"));
+
+        let downloaded = self.downloaded(anime);
+        let fields = vec![
+            ("Season", anime.season.to_string()),
+            (
+                "Status",
+                String::from(if anime.ongoing { "Airing" } else { "Finished" }),
+            ),
+            (
+                "Episodes",
+                anime
+                    .episode_count
+                    .map(|count| count.to_string())
+                    .unwrap_or_else(unknown),
+            ),
+            ("Downloaded", downloaded.to_string()),
+            (
+                "MAL ID",
+                anime
+                    .mal_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(unknown),
+            ),
+            (
+                "Added",
+                anime
+                    .created_at
+                    .map(|t| t.format("%Y-%m-%d").to_string())
+                    .unwrap_or_else(unknown),
+            ),
+            (
+                "Updated",
+                anime
+                    .updated_at
+                    .map(|t| t.format("%Y-%m-%d").to_string())
+                    .unwrap_or_else(unknown),
+            ),
+        ];
+
+        for (name, value) in fields {
+            rows.push(Text::styled(format!("{:<11}", name), label));
+            rows.push(Text::raw(format!("{}\n", value)));
+        }
+        rows
+    }
+}
@@ -1,6 +1,10 @@
 use super::statefull_list::StatefulList;
 
-use crate::types::Episode;
+use crate::{
+    player::timestamp,
+    types::{Episode, ID},
+    watched::Watched,
+};
 use std::{error::Error, io::Stdout};
 use tui::{
     backend::CrosstermBackend,
@@ -17,12 +21,36 @@ impl EpisodeList {
         &mut self,
         painter: &mut Frame<CrosstermBackend<Stdout>>,
         chunk: Rect,
+        anime: ID,
+        watched: &Watched,
+        downloaded: &[i64],
     ) -> Result<(), Box<dyn Error>> {
         let style = Style::default();
-        let items = self.items.iter().map(|i| Text::raw(i.number.to_string()));
+        let items = self.items.iter().map(|i| {
+            let seen = watched.is_watched(anime, i.number);
+            let on_disk = downloaded.contains(&i.number);
+            let mut label = format!(
+                "{} {:>4} {}",
+                if seen { "✓" } else { " " },
+                i.number,
+                if on_disk { "⬇" } else { " " }
+            );
+            if let Some(position) = watched.position(anime, i.number) {
+                label.push_str(&format!(" ⏵ {}", timestamp(position)));
+            }
+            if seen {
+                Text::styled(label, style.fg(Color::DarkGray))
+            } else {
+                Text::raw(label)
+            }
+        });
 
         let items = List::new(items)
-            .block(Block::default().borders(Borders::ALL).title("Episode list"))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Episode list (p: play, w/u: watched, W/U: up to here)"),
+            )
             .style(style)
             .highlight_style(style.fg(Color::LightGreen).modifier(Modifier::BOLD))
             .highlight_symbol(">");
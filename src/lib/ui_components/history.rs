@@ -0,0 +1,130 @@
+use super::statefull_list::StatefulList;
+
+use crate::{
+    downloads::{DownloadRecord, Outcome},
+    player::timestamp,
+    pretty_bytes::convert,
+};
+use chrono::{Duration, Local};
+use std::{error::Error, io::Stdout};
+use tui::{
+    backend::CrosstermBackend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::{Block, BorderType, Borders, List, Paragraph, Text},
+    Frame,
+};
+
+/// Periods summed up next to the download history.
+const PERIODS: &[(&str, i64)] = &[
+    ("Last 24 hours", 1),
+    ("Last 7 days", 7),
+    ("Last 30 days", 30),
+];
+
+#[derive(Debug, Clone)]
+pub struct HistoryView {
+    /// Newest first.
+    pub records: StatefulList<DownloadRecord>,
+}
+
+impl Default for HistoryView {
+    fn default() -> Self {
+        Self {
+            records: StatefulList::new(),
+        }
+    }
+}
+
+impl HistoryView {
+    /// Keeps the highlighted row, or the first one for a new history.
+    pub fn replace_records(&mut self, records: Vec<DownloadRecord>) {
+        let selected = self.records.state.selected();
+        self.records.items = records;
+        match (selected, self.records.items.len()) {
+            (_, 0) => self.records.state.select(None),
+            (Some(idx), len) => self.records.state.select(Some(idx.min(len - 1))),
+            (None, _) => self.records.state.select(Some(0)),
+        }
+    }
+
+    pub fn draw(
+        &mut self,
+        painter: &mut Frame<CrosstermBackend<Stdout>>,
+        summary_chunk: Rect,
+        records_chunk: Rect,
+    ) -> Result<(), Box<dyn Error>> {
+        let now = Local::now().naive_local();
+        let records = &self.records.items;
+        let mut summary = Vec::new();
+        let periods = PERIODS
+            .iter()
+            .map(|(name, days)| (*name, Some(now - Duration::days(*days))))
+            .chain(std::iter::once(("All time", None)));
+        for (name, since) in periods {
+            let within: Vec<_> = records
+                .iter()
+                .filter(|r| !matches!(since, Some(since) if r.finished_at < since))
+                .collect();
+            let completed: Vec<_> = within.iter().filter(|r| r.is_completed()).collect();
+            let failed = within.len() - completed.len();
+            summary.push(Text::styled(
+                format!("{}\n", name),
+                Style::default().modifier(Modifier::BOLD),
+            ));
+            summary.push(Text::raw(format!(
+                "  {} episodes, {}\n",
+                completed.len(),
+                convert(completed.iter().map(|r| r.size).sum())
+            )));
+            if failed > 0 {
+                summary.push(Text::styled(
+                    format!("  {} failed\n", failed),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+            summary.push(Text::raw("\n"));
+        }
+        let paragraph = Paragraph::new(summary.iter()).block(block("Downloads"));
+        painter.render_widget(paragraph, summary_chunk);
+
+        let items = records.iter().map(|record| {
+            let row = format!(
+                "{}  {} - {}  {}  {}  {}/s",
+                record.finished_at.format("%Y-%m-%d %H:%M"),
+                record.title,
+                record.episode,
+                convert(record.size),
+                timestamp(record.duration_ms as f64 / 1000.0),
+                convert(record.speed)
+            );
+            match &record.outcome {
+                Outcome::Completed => Text::raw(row),
+                Outcome::Failed(error) => Text::styled(
+                    format!("{}  failed: {}", row, error),
+                    Style::default().fg(Color::Red),
+                ),
+            }
+        });
+        let list = List::new(items)
+            .block(block("History (r: download again, Esc: back)"))
+            .highlight_style(
+                Style::default()
+                    .fg(Color::LightGreen)
+                    .modifier(Modifier::BOLD),
+            )
+            .highlight_symbol(">");
+        painter.render_stateful_widget(list, records_chunk, &mut self.records.state);
+
+        Ok(())
+    }
+}
+
+fn block(title: &str) -> Block<'_> {
+    Block::default()
+        .title_style(Style::default().fg(Color::Red))
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .style(Style::default().bg(Color::Black))
+        .title(title)
+}
@@ -0,0 +1,217 @@
+use super::statefull_list::StatefulList;
+
+use crate::{
+    library::{FileStatus, LibraryFile, Show},
+    pretty_bytes::convert,
+};
+use std::{error::Error, io::Stdout, path::PathBuf};
+use tui::{
+    backend::CrosstermBackend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::{Block, BorderType, Borders, List, Text},
+    Frame,
+};
+
+/// Which of the two library lists the keys go to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LibraryFocus {
+    #[default]
+    Shows,
+    Files,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LibraryView {
+    pub shows: StatefulList<Show>,
+    /// Files of the highlighted show.
+    pub files: StatefulList<LibraryFile>,
+    pub focus: LibraryFocus,
+    /// Paths waiting for a second `d` before they are deleted.
+    pub pending_delete: Vec<PathBuf>,
+    pub scanning: bool,
+}
+
+impl LibraryView {
+    /// Keeps the highlighted show and file across rescans.
+    pub fn replace_shows(&mut self, shows: Vec<Show>) {
+        let folder = self.selected_show().map(|show| show.folder.clone());
+        let file = self.files.state.selected();
+
+        self.shows.items = shows;
+        let idx = folder
+            .and_then(|folder| self.shows.items.iter().position(|s| s.folder == folder))
+            .unwrap_or(0);
+        self.shows
+            .state
+            .select(Some(idx).filter(|_| !self.shows.items.is_empty()));
+        self.show_changed();
+
+        if let (Some(file), false) = (file, self.files.items.is_empty()) {
+            let last = self.files.items.len() - 1;
+            self.files.state.select(Some(file.min(last)));
+        }
+    }
+
+    pub fn selected_show(&self) -> Option<&Show> {
+        self.shows
+            .state
+            .selected()
+            .and_then(|idx| self.shows.items.get(idx))
+    }
+
+    pub fn selected_file(&self) -> Option<&LibraryFile> {
+        self.files
+            .state
+            .selected()
+            .and_then(|idx| self.files.items.get(idx))
+    }
+
+    /// Moves the selection of the focused list `rows` down, or up if negative.
+    pub fn jump(&mut self, rows: isize) {
+        self.pending_delete.clear();
+        match self.focus {
+            LibraryFocus::Shows => {
+                self.shows.jump(rows);
+                self.show_changed();
+            }
+            LibraryFocus::Files => self.files.jump(rows),
+        }
+    }
+
+    /// Like `jump` by one row, but wraps around at the ends.
+    pub fn step(&mut self, down: bool) {
+        self.pending_delete.clear();
+        match (self.focus, down) {
+            (LibraryFocus::Shows, true) => self.shows.next(),
+            (LibraryFocus::Shows, false) => self.shows.previous(),
+            (LibraryFocus::Files, true) => self.files.next(),
+            (LibraryFocus::Files, false) => self.files.previous(),
+        }
+        if self.focus == LibraryFocus::Shows {
+            self.show_changed();
+        }
+    }
+
+    pub fn first(&mut self) {
+        let len = self.focused_len() as isize;
+        self.jump(-len);
+    }
+
+    pub fn last(&mut self) {
+        let len = self.focused_len() as isize;
+        self.jump(len);
+    }
+
+    fn focused_len(&self) -> usize {
+        match self.focus {
+            LibraryFocus::Shows => self.shows.items.len(),
+            LibraryFocus::Files => self.files.items.len(),
+        }
+    }
+
+    fn show_changed(&mut self) {
+        let files = self
+            .selected_show()
+            .map(|show| show.files.clone())
+            .unwrap_or_default();
+        self.files = StatefulList::with_items(files);
+        self.files.first();
+    }
+
+    pub fn draw(
+        &mut self,
+        painter: &mut Frame<CrosstermBackend<Stdout>>,
+        shows_chunk: Rect,
+        files_chunk: Rect,
+    ) -> Result<(), Box<dyn Error>> {
+        let title = if self.scanning {
+            "Library (scanning...)"
+        } else {
+            "Library"
+        };
+        let items = self.shows.items.iter().map(|show| {
+            let mut row = format!(
+                "{}  {} eps  {}",
+                show.title(),
+                show.count(FileStatus::Complete),
+                convert(show.size())
+            );
+            let partial = show.count(FileStatus::Partial);
+            let orphaned = show.count(FileStatus::Orphaned);
+            if partial > 0 {
+                row.push_str(&format!("  [{} partial]", partial));
+            }
+            if orphaned > 0 {
+                row.push_str(&format!("  [{} orphaned]", orphaned));
+            }
+            match (show.anime.is_some(), partial + orphaned) {
+                (false, _) => Text::styled(row, Style::default().fg(Color::Red)),
+                (true, 0) => Text::raw(row),
+                (true, _) => Text::styled(row, Style::default().fg(Color::Yellow)),
+            }
+        });
+        let list = List::new(items)
+            .block(block(title, self.focus == LibraryFocus::Shows))
+            .highlight_style(
+                Style::default()
+                    .fg(Color::LightGreen)
+                    .modifier(Modifier::BOLD),
+            )
+            .highlight_symbol(">");
+        painter.render_stateful_widget(list, shows_chunk, &mut self.shows.state);
+
+        let pending = &self.pending_delete;
+        let items = self.files.items.iter().map(|file| {
+            let name = file
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let mut row = format!("{:<12} {:>10}", name, convert(file.size));
+            if let Some(media) = &file.media {
+                row.push_str(&format!("  {}", media));
+            }
+            match file.status() {
+                _ if pending.contains(&file.path) => Text::styled(
+                    format!("{}  press d again to delete", row),
+                    Style::default().fg(Color::Red).modifier(Modifier::BOLD),
+                ),
+                FileStatus::Complete => Text::raw(row),
+                FileStatus::Partial => Text::styled(
+                    format!("{}  partial", row),
+                    Style::default().fg(Color::Yellow),
+                ),
+                FileStatus::Orphaned => Text::styled(
+                    format!("{}  orphaned", row),
+                    Style::default().fg(Color::Red),
+                ),
+            }
+        });
+        let list = List::new(items)
+            .block(block(
+                "Files (d: delete, r: download again)",
+                self.focus == LibraryFocus::Files,
+            ))
+            .highlight_style(
+                Style::default()
+                    .fg(Color::LightGreen)
+                    .modifier(Modifier::BOLD),
+            )
+            .highlight_symbol(">");
+        painter.render_stateful_widget(list, files_chunk, &mut self.files.state);
+
+        Ok(())
+    }
+}
+
+fn block(title: &str, focused: bool) -> Block<'_> {
+    let border = if focused { Color::Yellow } else { Color::White };
+    Block::default()
+        .title_style(Style::default().fg(Color::Red))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(border))
+        .border_type(BorderType::Rounded)
+        .style(Style::default().bg(Color::Black))
+        .title(title)
+}
@@ -1,5 +1,8 @@
 pub mod anime;
+pub mod details;
 pub mod episodes;
+pub mod history;
+pub mod library;
 pub mod notifications;
 pub mod progress;
 pub mod search;
@@ -1,4 +1,4 @@
-use crate::pretty_bytes::convert;
+use crate::{pretty_bytes::convert, schedule::Status};
 use std::{error::Error, io::Stdout};
 use tui::{
     backend::CrosstermBackend,
@@ -8,6 +8,14 @@ use tui::{
     Frame,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DownloadProgress {
+    /// Bytes received and the size of the file.
+    Bytes(u64, u64),
+    /// Segments done, segments in the playlist and bytes received.
+    Segments(usize, usize, u64),
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Progress {}
 
@@ -16,21 +24,43 @@ impl Progress {
         &mut self,
         painter: &mut Frame<CrosstermBackend<Stdout>>,
         chunk: Rect,
-        download_bytes: u64,
-        total_size: u64,
+        progress: Option<DownloadProgress>,
+        schedule: Status,
     ) -> Result<(), Box<dyn Error>> {
-        let progress = download_bytes as f64 / total_size as f64;
-        let progress = if progress > 1.0 { 1.0 } else { progress };
+        let (progress, label) = match progress {
+            Some(DownloadProgress::Bytes(download_bytes, total_size)) => {
+                let progress = download_bytes as f64 / total_size as f64;
+                let progress = if progress > 1.0 { 1.0 } else { progress };
+
+                let download_bytes = convert(download_bytes);
+                let total_size = convert(total_size);
 
-        let download_bytes = convert(download_bytes);
-        let total_size = convert(total_size);
+                let label = format!(
+                    "{:.2}% \t {} / {}",
+                    progress * 100.0,
+                    download_bytes,
+                    total_size
+                );
+                (progress, label)
+            }
+            Some(DownloadProgress::Segments(done, total, bytes)) => {
+                let progress = done as f64 / total.max(1) as f64;
+                let label = format!(
+                    "{:.2}% \t segment {} / {} \t {}",
+                    progress * 100.0,
+                    done,
+                    total,
+                    convert(bytes)
+                );
+                (progress, label)
+            }
+            None => (0.0, String::from("Idle")),
+        };
 
-        let label = format!(
-            "{:.2}% \t {} / {}",
-            progress * 100.0,
-            download_bytes,
-            total_size
-        );
+        let title = match schedule {
+            Status::Always => String::from("Download:"),
+            status => format!("Download ({}):", status),
+        };
 
         let block = Block::default()
             .title_style(Style::default().fg(Color::Red))
@@ -38,7 +68,7 @@ impl Progress {
             .border_style(Style::default().fg(Color::White))
             .border_type(BorderType::Rounded)
             .style(Style::default().bg(Color::Black))
-            .title("Download:");
+            .title(&title);
 
         let gauge = Gauge::default()
             .block(block)
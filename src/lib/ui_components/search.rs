@@ -1,38 +1,287 @@
+use crate::{query::ParseError, search::MatchMode};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::{error::Error, io::Stdout};
 use tui::{
     backend::CrosstermBackend,
     layout::{Alignment, Rect},
-    style::{Color, Style},
+    style::{Color, Modifier, Style},
     widgets::{Block, BorderType, Borders, Paragraph, Text},
     Frame,
 };
+use unicode_width::UnicodeWidthStr;
 
+/// What a key press did to the search box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Changed,
+    Unchanged,
+    Ignored,
+}
+
+/// Single line editor behind the search box.
 #[derive(Debug, Default, Clone)]
-pub struct Search {}
+pub struct Search {
+    query: String,
+    /// Cursor position in chars.
+    cursor: usize,
+    /// First visible char when the query is wider than the box.
+    scroll: usize,
+    /// Position in the history while browsing it with Up and Down.
+    recalled: Option<usize>,
+}
 
 impl Search {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn set_query(&mut self, query: &str) {
+        self.query = query.to_string();
+        self.cursor = self.query.chars().count();
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) -> Edit {
+        let edit = self.edit(key);
+        if edit == Edit::Changed {
+            self.recalled = None;
+        }
+        edit
+    }
+
+    /// Up on an empty box starts walking `history` (newest first), Down walks
+    /// back until the box is empty again. Ignored when not browsing, so the
+    /// keys can move the list instead.
+    pub fn recall<S: AsRef<str>>(&mut self, history: &[S], older: bool) -> Edit {
+        let next = match (self.recalled, older) {
+            _ if history.is_empty() => return Edit::Ignored,
+            (None, true) if self.query.is_empty() => Some(0),
+            (None, _) => return Edit::Ignored,
+            (Some(i), true) => Some((i + 1).min(history.len() - 1)),
+            (Some(0), false) => None,
+            (Some(i), false) => Some(i - 1),
+        };
+        self.recalled = next;
+        self.set_query(next.map_or("", |i| history[i].as_ref()));
+        Edit::Changed
+    }
+
+    fn edit(&mut self, key: KeyEvent) -> Edit {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('w') if ctrl => self.delete_word(),
+            KeyCode::Char('u') if ctrl => self.delete_to_start(),
+            KeyCode::Char(_) if ctrl => Edit::Ignored,
+            KeyCode::Char(c) => {
+                self.insert(c);
+                Edit::Changed
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.remove(self.cursor);
+                Edit::Changed
+            }
+            KeyCode::Delete if self.cursor < self.len() => {
+                self.remove(self.cursor);
+                Edit::Changed
+            }
+            KeyCode::Left => self.move_to(self.cursor.saturating_sub(1)),
+            KeyCode::Right => self.move_to(self.cursor + 1),
+            KeyCode::Home => self.move_to(0),
+            KeyCode::End => self.move_to(self.len()),
+            KeyCode::Backspace | KeyCode::Delete => Edit::Unchanged,
+            _ => Edit::Ignored,
+        }
+    }
+
+    /// Inserts pasted text, line breaks and other control chars become spaces.
+    pub fn paste(&mut self, text: &str) -> Edit {
+        self.recalled = None;
+        for c in text.chars() {
+            self.insert(if c.is_control() { ' ' } else { c });
+        }
+        Edit::Changed
+    }
+
+    fn len(&self) -> usize {
+        self.query.chars().count()
+    }
+
+    fn byte_offset(&self, cursor: usize) -> usize {
+        self.query
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.query.len(), |(i, _)| i)
+    }
+
+    fn insert(&mut self, c: char) {
+        let offset = self.byte_offset(self.cursor);
+        self.query.insert(offset, c);
+        self.cursor += 1;
+    }
+
+    fn remove(&mut self, cursor: usize) {
+        let offset = self.byte_offset(cursor);
+        self.query.remove(offset);
+    }
+
+    fn move_to(&mut self, cursor: usize) -> Edit {
+        self.cursor = cursor.min(self.len());
+        Edit::Unchanged
+    }
+
+    fn delete_word(&mut self) -> Edit {
+        let chars: Vec<char> = self.query.chars().collect();
+        let mut start = self.cursor;
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.delete_range(start)
+    }
+
+    fn delete_to_start(&mut self) -> Edit {
+        self.delete_range(0)
+    }
+
+    fn delete_range(&mut self, start: usize) -> Edit {
+        if start == self.cursor {
+            return Edit::Unchanged;
+        }
+        let (from, to) = (self.byte_offset(start), self.byte_offset(self.cursor));
+        self.query.replace_range(from..to, "");
+        self.cursor = start;
+        Edit::Changed
+    }
+
+    /// Terminal position of the cursor for a box drawn in `chunk`.
+    pub fn cursor_position(&self, chunk: Rect) -> (u16, u16) {
+        let before: String = self
+            .query
+            .chars()
+            .skip(self.scroll)
+            .take(self.cursor.saturating_sub(self.scroll))
+            .collect();
+        let x = chunk.x + 1 + before.width() as u16;
+        (x.min(chunk.right().saturating_sub(2)), chunk.y + 1)
+    }
+
     pub fn draw(
         &mut self,
         painter: &mut Frame<CrosstermBackend<Stdout>>,
         chunk: Rect,
-        query: &str,
+        error: Option<&ParseError>,
+        mode: MatchMode,
+        focused: bool,
     ) -> Result<(), Box<dyn Error>> {
+        let title = match mode {
+            MatchMode::Fuzzy => "SearchBox (fuzzy)",
+            MatchMode::Prefix => "SearchBox (prefix)",
+        };
+        let border = if focused { Color::Yellow } else { Color::White };
+
         let block = Block::default()
             .title_style(Style::default().fg(Color::Red))
             .borders(Borders::ALL)
-            .border_style(Style::default().fg(Color::White))
+            .border_style(Style::default().fg(border))
             .border_type(BorderType::Rounded)
             .style(Style::default().bg(Color::Black))
-            .title("SearchBox");
+            .title(title);
 
-        let paragraph = [Text::raw(query)];
+        self.scroll_to_cursor(chunk.width.saturating_sub(3) as usize);
+        let scroll = self.byte_offset(self.scroll);
+        let query = &self.query[scroll..];
+
+        // Underline the query from the offending term on and tell why.
+        let paragraph = match error {
+            Some(error) if error.position <= self.query.len() => {
+                let (valid, invalid) = self.query.split_at(error.position.max(scroll));
+                let valid = &valid[scroll.min(valid.len())..];
+                let style = Style::default().fg(Color::Red);
+                vec![
+                    Text::raw(valid),
+                    Text::styled(invalid, style.modifier(Modifier::UNDERLINED)),
+                    Text::styled(format!("  {}", error.message), style),
+                ]
+            }
+            _ => vec![Text::raw(query)],
+        };
         let paragraph = Paragraph::new(paragraph.iter())
             .block(block)
-            .alignment(Alignment::Center)
-            .wrap(true);
+            .alignment(Alignment::Left)
+            .wrap(false);
 
         painter.render_widget(paragraph, chunk);
 
         Ok(())
     }
+
+    fn scroll_to_cursor(&mut self, width: usize) {
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        }
+        loop {
+            let visible: String = self
+                .query
+                .chars()
+                .skip(self.scroll)
+                .take(self.cursor - self.scroll)
+                .collect();
+            if visible.width() < width.max(1) || self.scroll >= self.cursor {
+                break;
+            }
+            self.scroll += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HISTORY: [&str; 3] = ["mushishi", "kyojin", "ryokou"];
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn up_walks_back_and_stops_at_the_oldest() {
+        let mut search = Search::default();
+        assert_eq!(search.recall(&HISTORY, true), Edit::Changed);
+        assert_eq!(search.query(), "mushishi");
+        search.recall(&HISTORY, true);
+        search.recall(&HISTORY, true);
+        search.recall(&HISTORY, true);
+        assert_eq!(search.query(), "ryokou");
+    }
+
+    #[test]
+    fn down_walks_forward_to_an_empty_box() {
+        let mut search = Search::default();
+        search.recall(&HISTORY, true);
+        search.recall(&HISTORY, true);
+        search.recall(&HISTORY, false);
+        assert_eq!(search.query(), "mushishi");
+        assert_eq!(search.recall(&HISTORY, false), Edit::Changed);
+        assert_eq!(search.query(), "");
+        assert_eq!(search.recall(&HISTORY, false), Edit::Ignored);
+    }
+
+    #[test]
+    fn recall_leaves_typed_queries_alone() {
+        let mut search = Search::default();
+        assert_eq!(search.recall(&[] as &[&str], true), Edit::Ignored);
+        search.on_key(key('m'));
+        assert_eq!(search.recall(&HISTORY, true), Edit::Ignored);
+        assert_eq!(search.query(), "m");
+
+        // Typing after recalling starts a new query.
+        let mut search = Search::default();
+        search.recall(&HISTORY, true);
+        search.on_key(key('!'));
+        assert_eq!(search.recall(&HISTORY, true), Edit::Ignored);
+        assert_eq!(search.query(), "mushishi!");
+    }
 }
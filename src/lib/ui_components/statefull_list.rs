@@ -3,6 +3,8 @@ use tui::widgets::ListState;
 pub struct StatefulList<T> {
     pub state: ListState,
     pub items: Vec<T>,
+    /// Scroll position for lists that render themselves instead of using `List`.
+    pub offset: usize,
 }
 
 impl<T> StatefulList<T> {
@@ -10,6 +12,7 @@ impl<T> StatefulList<T> {
         StatefulList {
             state: ListState::default(),
             items: Vec::new(),
+            offset: 0,
         }
     }
 
@@ -17,10 +20,14 @@ impl<T> StatefulList<T> {
         StatefulList {
             state: ListState::default(),
             items,
+            offset: 0,
         }
     }
 
     pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.items.len() - 1 {
@@ -35,6 +42,9 @@ impl<T> StatefulList<T> {
     }
 
     pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -48,6 +58,29 @@ impl<T> StatefulList<T> {
         self.state.select(Some(i));
     }
 
+    pub fn first(&mut self) {
+        if !self.items.is_empty() {
+            self.state.select(Some(0));
+        }
+    }
+
+    pub fn last(&mut self) {
+        if !self.items.is_empty() {
+            self.state.select(Some(self.items.len() - 1));
+        }
+    }
+
+    /// Moves the selection `rows` down (or up if negative), stopping at the ends.
+    pub fn jump(&mut self, rows: isize) {
+        if self.items.is_empty() {
+            return;
+        }
+        let current = self.state.selected().unwrap_or(0) as isize;
+        let last = self.items.len() as isize - 1;
+        let i = (current + rows).max(0).min(last);
+        self.state.select(Some(i as usize));
+    }
+
     pub fn unselect(&mut self) {
         self.state.select(None);
     }
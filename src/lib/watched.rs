@@ -0,0 +1,92 @@
+use crate::types::{Episode, ID};
+use serde::{Deserialize, Serialize};
+use serde_json::{de, ser};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+};
+
+pub static WATCHED_PATH: &str = "./.cache/watched.json";
+
+/// Watched episode numbers per anime, and where unfinished ones were left.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Watched {
+    pub animes: HashMap<ID, BTreeSet<i64>>,
+    /// Resume positions in seconds.
+    pub positions: HashMap<ID, BTreeMap<i64, f64>>,
+}
+
+impl Watched {
+    pub fn load() -> Result<Watched, Box<dyn Error>> {
+        let s = read_to_string(WATCHED_PATH)?;
+        let watched: Watched = de::from_str(&s)?;
+        Ok(watched)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(WATCHED_PATH);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, ser::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_watched(&self, id: ID, number: i64) -> bool {
+        matches!(self.animes.get(&id), Some(watched) if watched.contains(&number))
+    }
+
+    pub fn set(&mut self, id: ID, number: i64, watched: bool) {
+        let numbers = self.animes.entry(id).or_default();
+        if watched {
+            numbers.insert(number);
+        } else {
+            numbers.remove(&number);
+        }
+        if numbers.is_empty() {
+            self.animes.remove(&id);
+        }
+    }
+
+    pub fn position(&self, id: ID, number: i64) -> Option<f64> {
+        self.positions.get(&id)?.get(&number).copied()
+    }
+
+    pub fn set_position(&mut self, id: ID, number: i64, position: Option<f64>) {
+        let positions = self.positions.entry(id).or_default();
+        match position {
+            Some(position) => positions.insert(number, position),
+            None => positions.remove(&number),
+        };
+        if positions.is_empty() {
+            self.positions.remove(&id);
+        }
+    }
+
+    /// Marks every episode numbered up to and including `number`.
+    pub fn set_up_to(&mut self, id: ID, episodes: &[Episode], number: i64, watched: bool) {
+        for episode in episodes.iter().filter(|e| e.number <= number) {
+            self.set(id, episode.number, watched);
+        }
+    }
+
+    pub fn set_all(&mut self, id: ID, episodes: &[Episode], watched: bool) {
+        for episode in episodes {
+            self.set(id, episode.number, watched);
+        }
+    }
+
+    /// Lowest episode number among `numbers` that hasn't been watched.
+    pub fn first_unwatched<I>(&self, id: ID, numbers: I) -> Option<i64>
+    where
+        I: IntoIterator<Item = i64>,
+    {
+        numbers
+            .into_iter()
+            .filter(|number| !self.is_watched(id, *number))
+            .min()
+    }
+}
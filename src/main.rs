@@ -1,7 +1,27 @@
-use library::ui::App;
+use library::{cli, config::Config, ui::App};
+use std::{env, process};
 
 #[tokio::main]
 async fn main() {
-    let mut app = App::new();
-    let _ = app.start().await;
+    // A broken config.json is reported before anything starts, the
+    // interface has no room for the error once it's up.
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        let mut app = App::new(config);
+        let _ = app.start().await;
+        return;
+    }
+
+    if let Err(e) = cli::run(&args).await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }